@@ -279,6 +279,13 @@ declare namespace Deno {
      *
      * @category Errors */
     export class Http extends Error {}
+    /**
+     * Raised when a request body read by {@linkcode Deno.serveHttp} grows
+     * past the `maxBodySize` connection option. Handlers can answer it with
+     * a `413 Content Too Large`.
+     *
+     * @category Errors */
+    export class RequestBodyTooLarge extends Http {}
     /**
      * Raised when the underlying IO resource is not available because it is
      * being awaited on in another block of code.
@@ -5657,6 +5664,104 @@ declare namespace Deno {
     close(): void;
   }
 
+  /**
+   * Options for the connection served by {@linkcode serveHttp}. Durations are
+   * given in milliseconds, and sizes in bytes. Every option is off, or left
+   * to the HTTP implementation's default, when it is not set.
+   *
+   * @category HTTP Server
+   *
+   * @deprecated This will be removed in Deno 2.0. See the
+   * {@link https://docs.deno.com/runtime/manual/advanced/migrate_deprecations | Deno 1.x to 2.x Migration Guide}
+   * for migration instructions.
+   */
+  export interface HttpConnOptions {
+    /** Shut the connection down gracefully once no request has been
+     * dispatched on it for this long. */
+    keepAliveTimeout?: number;
+    /** Close an HTTP/1 connection if a request head is not fully received
+     * within this long. The timer starts at the first byte of the head, so
+     * connections idling between requests are not affected. */
+    headerReadTimeout?: number;
+    /** Fail a request body read if no data arrives within this long. */
+    bodyReadTimeout?: number;
+    /** Fail a response body write and drop the connection if the client has
+     * not taken the data within this long. */
+    bodyWriteTimeout?: number;
+    /** Reading more than this many bytes from a request body fails, so the
+     * handler can answer with a `413`. */
+    maxBodySize?: number;
+    /** Decode request bodies sent with a `Content-Encoding` of `gzip`,
+     * `deflate` or `br`. Those requests lose their `Content-Encoding` and
+     * `Content-Length` headers. */
+    decompressRequestBody?: boolean;
+    /** Read request bodies of up to this many bytes together with the
+     * request when they have fully arrived by then. */
+    inlineBodySize?: number;
+    /** Hold back small chunks of a streamed response body until this many
+     * bytes have been written, and send them as one. */
+    writeBufferSize?: number;
+    /** Answer requests with more header fields than this with a `431`. */
+    maxHeaders?: number;
+    /** Largest request head accepted. Must be at least 8192. */
+    maxHeaderSize?: number;
+    /** Whether HTTP/1 responses are written with vectored writes. */
+    http1Writev?: boolean;
+    /** Answer HTTP/1 requests carrying both `Content-Length` and
+     * `Transfer-Encoding` with a `400`. */
+    strictFraming?: boolean;
+    /** Brotli quality level for compressed responses, from 0 to 11. */
+    brotliQuality?: number;
+    /** Brotli window size as a power of two, from 10 to 24. */
+    brotliWindow?: number;
+    /** Return every `Cookie` request header as its own entry instead of
+     * joining them into a single header. */
+    separateCookies?: boolean;
+    /** Serve plaintext connections as HTTP/2 only (h2c with prior
+     * knowledge). */
+    http2PriorKnowledge?: boolean;
+    /** HTTP/2 flow control window of each stream. */
+    http2InitialStreamWindowSize?: number;
+    /** HTTP/2 flow control window of the connection. */
+    http2InitialConnectionWindowSize?: number;
+    /** Largest HTTP/2 frame payload the client may send. */
+    http2MaxFrameSize?: number;
+    /** Number of HTTP/2 streams the client may have open at once. */
+    http2MaxConcurrentStreams?: number;
+    /** Advertise the extended CONNECT protocol (RFC 8441) to HTTP/2
+     * clients. */
+    http2EnableConnectProtocol?: boolean;
+    /** Expect plaintext connections to start with a PROXY protocol header,
+     * and report the client address it carries. */
+    proxyProtocol?: boolean;
+    /** Build request URLs with the scheme from the `Forwarded` or
+     * `X-Forwarded-Proto` request header. Only enable this behind a proxy
+     * that sets those headers itself. */
+    trustForwardedProto?: boolean;
+    /** Disable Nagle's algorithm on TCP connections. */
+    tcpNodelay?: boolean;
+    /** Idle time before TCP keepalive probes are sent. */
+    tcpKeepalive?: number;
+    /** Size of the socket send buffer of TCP connections. */
+    sendBufferSize?: number;
+    /** Size of the socket receive buffer of TCP connections. */
+    recvBufferSize?: number;
+    /** Headers added to every response that does not set them itself. */
+    defaultHeaders?: [string, string][];
+    /** Answer `GET` and `HEAD` requests for `path` with a static response,
+     * without yielding them from the {@linkcode HttpConn}. */
+    healthCheck?: { path: string; status?: number; body?: string };
+    /** Changes applied, in order, to the headers of every request. */
+    headerTransforms?: (
+      | { type: "rename"; from: string; to: string }
+      | { type: "default"; name: string; value: string }
+      | { type: "strip"; name: string }
+    )[];
+    /** Answer requests beyond this rate with a `429`. `burst` is how many
+     * requests can arrive at once after the connection was idle. */
+    rateLimit?: { requestsPerSecond: number; burst?: number };
+  }
+
   /**
    * Provides an interface to handle HTTP request and responses over TCP or TLS
    * connections. The method returns an {@linkcode HttpConn} which yields up
@@ -5707,7 +5812,10 @@ declare namespace Deno {
    * {@link https://docs.deno.com/runtime/manual/advanced/migrate_deprecations | Deno 1.x to 2.x Migration Guide}
    * for migration instructions.
    */
-  export function serveHttp(conn: Conn): HttpConn;
+  export function serveHttp(
+    conn: Conn,
+    options?: HttpConnOptions,
+  ): HttpConn;
 
   /** The object that is returned from a {@linkcode Deno.upgradeWebSocket}
    * request.
@@ -5741,6 +5849,17 @@ declare namespace Deno {
      * The unit is seconds, with a default of 120.
      * Set to `0` to disable timeouts. */
     idleTimeout?: number;
+    /** The largest message, in bytes, accepted from the client, counting all
+     * of its fragments. The web socket closes with an error on the first
+     * fragment that takes a message past this size. Unlimited by default.
+     *
+     * Only applies to web sockets upgraded from {@linkcode Deno.serveHttp}. */
+    maxMessageSize?: number;
+    /** The largest frame payload, in bytes, accepted from the client. A larger
+     * frame closes the web socket with an error. Defaults to 64 MiB.
+     *
+     * Only applies to web sockets upgraded from {@linkcode Deno.serveHttp}. */
+    maxFrameSize?: number;
   }
 
   /**
@@ -29,7 +29,11 @@ const {
   TypedArrayPrototypeGetSymbolToStringTag,
   Uint8Array,
 } = primordials;
-import { _ws } from "ext:deno_http/02_websocket.ts";
+import {
+  _maxFrameSize,
+  _maxMessageSize,
+  _ws,
+} from "ext:deno_http/02_websocket.ts";
 import { InnerBody } from "ext:deno_fetch/22_body.js";
 import { Event } from "ext:deno_web/02_event.js";
 import { BlobPrototype } from "ext:deno_web/09_file.js";
@@ -44,6 +48,7 @@ import {
 } from "ext:deno_fetch/23_request.js";
 import {
   _eventLoop,
+  _extensions,
   _idleTimeoutDuration,
   _idleTimeoutTimeout,
   _protocol,
@@ -126,25 +131,38 @@ class HttpConn {
       return null;
     }
 
-    const { 0: readStreamRid, 1: writeStreamRid, 2: method, 3: url } =
-      nextRequest;
+    const {
+      0: readStreamRid,
+      1: writeStreamRid,
+      2: method,
+      3: url,
+      5: inlineBody,
+      6: bodySize,
+    } = nextRequest;
     SetPrototypeAdd(this.#managedResources, readStreamRid);
     SetPrototypeAdd(this.#managedResources, writeStreamRid);
 
-    /** @type {ReadableStream<Uint8Array> | undefined} */
+    /** @type {ReadableStream<Uint8Array> | { body: Uint8Array, consumed: boolean } | undefined} */
     let body = null;
     // There might be a body, but we don't expose it for GET/HEAD requests.
     // It will be closed automatically once the request has been handled and
     // the response has been sent.
-    if (method !== "GET" && method !== "HEAD") {
+    if (inlineBody != null) {
+      body = { body: inlineBody, consumed: false };
+    } else if (method !== "GET" && method !== "HEAD") {
       body = readableStreamForRid(readStreamRid, false);
     }
 
+    let innerBody = null;
+    if (body !== null) {
+      innerBody = new InnerBody(body);
+      innerBody.length = bodySize;
+    }
     const innerRequest = newInnerRequest(
       method,
       url,
       () => op_http_headers(readStreamRid),
-      body !== null ? new InnerBody(body) : null,
+      innerBody,
       false,
     );
     const request = fromInnerRequest(
@@ -359,11 +377,15 @@ function createRespondWith(
 
       const ws = resp[_ws];
       if (ws) {
-        const wsRid = await op_http_upgrade_websocket(
-          readStreamRid,
-        );
+        const { rid: wsRid, protocol, extensions } =
+          await op_http_upgrade_websocket(
+            readStreamRid,
+            ws[_maxMessageSize],
+            ws[_maxFrameSize],
+          );
         ws[_rid] = wsRid;
-        ws[_protocol] = resp.headers.get("sec-websocket-protocol");
+        ws[_protocol] = protocol;
+        ws[_extensions] = extensions;
 
         httpConn.close();
 
@@ -395,13 +417,13 @@ function createRespondWith(
   };
 }
 
-function serveHttp(conn) {
+function serveHttp(conn, options = undefined) {
   internals.warnOnDeprecatedApi(
     "Deno.serveHttp()",
     new Error().stack,
     "Use `Deno.serve()` instead.",
   );
-  const rid = op_http_start(conn[internalRidSymbol]);
+  const rid = op_http_start(conn[internalRidSymbol], options);
   return new HttpConn(rid, conn.remoteAddr, conn.localAddr);
 }
 
@@ -9,6 +9,7 @@ const {
   StringPrototypeSplit,
   StringPrototypeToLowerCase,
   StringPrototypeToUpperCase,
+  StringPrototypeTrim,
   TypeError,
   Symbol,
 } = primordials;
@@ -33,6 +34,8 @@ import {
 } from "ext:deno_websocket/01_websocket.js";
 
 const _ws = Symbol("[[associated_ws]]");
+const _maxMessageSize = Symbol("[[max_message_size]]");
+const _maxFrameSize = Symbol("[[max_frame_size]]");
 
 const websocketCvf = buildCaseInsensitiveCommaValueFinder("websocket");
 const upgradeCvf = buildCaseInsensitiveCommaValueFinder("upgrade");
@@ -73,8 +76,13 @@ function upgradeWebSocket(request, options = {}) {
     ["sec-websocket-accept", accept],
   ];
 
+  // The header is a comma separated list, which may also have been sent as
+  // several header lines; `Headers` joins those with ", ".
   const protocolsStr = request.headers.get("sec-websocket-protocol") || "";
-  const protocols = StringPrototypeSplit(protocolsStr, ", ");
+  const protocols = ArrayPrototypeMap(
+    StringPrototypeSplit(protocolsStr, ","),
+    (protocol) => StringPrototypeTrim(protocol),
+  );
   if (protocols && options.protocol) {
     if (ArrayPrototypeIncludes(protocols, options.protocol)) {
       ArrayPrototypePush(r.headerList, [
@@ -93,6 +101,8 @@ function upgradeWebSocket(request, options = {}) {
   socket[_server] = true;
   socket[_idleTimeoutDuration] = options.idleTimeout ?? 120;
   socket[_idleTimeoutTimeout] = null;
+  socket[_maxMessageSize] = options.maxMessageSize;
+  socket[_maxFrameSize] = options.maxFrameSize;
 
   if (inner._wantsUpgrade) {
     return inner._wantsUpgrade("upgradeWebSocket", r, socket);
@@ -182,4 +192,4 @@ function buildCaseInsensitiveCommaValueFinder(checkText) {
 internals.buildCaseInsensitiveCommaValueFinder =
   buildCaseInsensitiveCommaValueFinder;
 
-export { _ws, upgradeWebSocket };
+export { _maxFrameSize, _maxMessageSize, _ws, upgradeWebSocket };
@@ -73,6 +73,51 @@ pub fn preferred(
   Ok(preferred_encoding)
 }
 
+/// Select the `supported` encoding with the highest qval, breaking ties by
+/// the order of `supported`. Encodings that are not listed take the qval of
+/// `*`, if present, and a qval of 0 excludes an encoding. Falls back to
+/// `Identity` when nothing is acceptable or the client prefers `identity`.
+pub fn negotiate(
+  encodings: impl Iterator<Item = Result<(Option<Encoding>, f32), EncodingError>>,
+  supported: &[Encoding],
+) -> Result<Encoding, EncodingError> {
+  let mut wildcard_qval = None;
+  let mut identity_qval = None;
+  let mut qvals = vec![None; supported.len()];
+
+  for r in encodings {
+    let (encoding, qval) = r?;
+    match encoding {
+      None => {
+        wildcard_qval.get_or_insert(qval);
+      }
+      Some(Encoding::Identity) => {
+        identity_qval.get_or_insert(qval);
+      }
+      Some(encoding) => {
+        if let Some(i) = supported.iter().position(|e| *e == encoding) {
+          qvals[i].get_or_insert(qval);
+        }
+      }
+    }
+  }
+
+  let mut preferred_encoding = Encoding::Identity;
+  let mut max_qval = 0.0;
+  for (encoding, qval) in supported.iter().zip(qvals) {
+    let qval = qval.or(wildcard_qval).unwrap_or(0.0);
+    if qval > max_qval {
+      preferred_encoding = *encoding;
+      max_qval = qval;
+    }
+  }
+
+  match identity_qval {
+    Some(qval) if qval > max_qval => Ok(Encoding::Identity),
+    _ => Ok(preferred_encoding),
+  }
+}
+
 /// Parse a set of HTTP headers into an iterator containing tuples of options containing encodings and their corresponding q-values.
 ///
 /// Compatible with `http` crate for version 0.2.x.
@@ -234,4 +279,43 @@ mod tests {
     assert_eq!(encodings[0], (Some(Encoding::Zstd), 1.0));
     assert_eq!(encodings[1], (Some(Encoding::Brotli), 0.9));
   }
+
+  fn negotiate_supported(value: &str) -> Encoding {
+    let mut headers = HeaderMap::new();
+    headers.insert(ACCEPT_ENCODING, HeaderValue::from_str(value).unwrap());
+    let supported = [Encoding::Brotli, Encoding::Gzip, Encoding::Deflate];
+    negotiate(encodings_iter_http_02(&headers), &supported).unwrap()
+  }
+
+  #[test]
+  fn negotiate_prefers_supported_order() {
+    assert_eq!(negotiate_supported("deflate, gzip, br"), Encoding::Brotli);
+    assert_eq!(negotiate_supported("deflate, gzip"), Encoding::Gzip);
+    assert_eq!(negotiate_supported("deflate"), Encoding::Deflate);
+    assert_eq!(negotiate_supported("zstd"), Encoding::Identity);
+  }
+
+  #[test]
+  fn negotiate_by_qval() {
+    assert_eq!(
+      negotiate_supported("br;q=0.5, gzip;q=0.8, deflate;q=0.2"),
+      Encoding::Gzip
+    );
+    assert_eq!(negotiate_supported("br;q=0, gzip"), Encoding::Gzip);
+    assert_eq!(
+      negotiate_supported("br;q=0, gzip;q=0, deflate;q=0"),
+      Encoding::Identity
+    );
+    assert_eq!(
+      negotiate_supported("identity, gzip;q=0.5"),
+      Encoding::Identity
+    );
+  }
+
+  #[test]
+  fn negotiate_wildcard() {
+    assert_eq!(negotiate_supported("*"), Encoding::Brotli);
+    assert_eq!(negotiate_supported("br;q=0, *;q=0.5"), Encoding::Gzip);
+    assert_eq!(negotiate_supported("deflate, *;q=0"), Encoding::Deflate);
+  }
 }
@@ -112,10 +112,10 @@ pub const UNSTABLE_FEATURE_NAME: &str = "http";
 const HTTP2_PREFIX: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
 
 /// ALPN negotiation for "h2"
-const TLS_ALPN_HTTP_2: &[u8] = b"h2";
+pub(crate) const TLS_ALPN_HTTP_2: &[u8] = b"h2";
 
 /// ALPN negotiation for "http/1.1"
-const TLS_ALPN_HTTP_11: &[u8] = b"http/1.1";
+pub(crate) const TLS_ALPN_HTTP_11: &[u8] = b"http/1.1";
 
 /// Name a trait for streams we can serve HTTP over.
 trait HttpServeStream:
@@ -253,7 +253,7 @@ pub async fn op_http_upgrade_websocket_next(
 
   // Stage 3: take the extracted raw network stream and upgrade it to a websocket, then return it
   let (stream, bytes) = extract_network_stream(upgraded);
-  ws_create_server_stream(&mut state.borrow_mut(), stream, bytes)
+  ws_create_server_stream(&mut state.borrow_mut(), stream, bytes, None, None)
 }
 
 #[op2(fast)]
@@ -1,29 +1,38 @@
 // Copyright 2018-2024 the Deno authors. All rights reserved. MIT license.
 
+use async_compression::brotli::EncoderParams;
+use async_compression::tokio::bufread::BrotliDecoder;
+use async_compression::tokio::bufread::GzipDecoder;
+use async_compression::tokio::bufread::ZlibDecoder;
 use async_compression::tokio::write::BrotliEncoder;
 use async_compression::tokio::write::GzipEncoder;
+use async_compression::tokio::write::ZlibEncoder;
 use async_compression::Level;
 use base64::prelude::BASE64_STANDARD;
 use base64::Engine;
+use bytes::BytesMut;
 use cache_control::CacheControl;
 use deno_core::error::custom_error;
+use deno_core::error::type_error;
 use deno_core::error::AnyError;
 use deno_core::futures::channel::mpsc;
 use deno_core::futures::channel::oneshot;
 use deno_core::futures::future::pending;
+use deno_core::futures::future::poll_fn;
 use deno_core::futures::future::select;
 use deno_core::futures::future::Either;
-use deno_core::futures::future::Pending;
 use deno_core::futures::future::RemoteHandle;
 use deno_core::futures::future::Shared;
-use deno_core::futures::never::Never;
 use deno_core::futures::ready;
+use deno_core::futures::stream::FusedStream;
 use deno_core::futures::stream::Peekable;
 use deno_core::futures::FutureExt;
 use deno_core::futures::StreamExt;
 use deno_core::futures::TryFutureExt;
+use deno_core::futures::TryStreamExt;
 use deno_core::op2;
 use deno_core::unsync::spawn;
+use deno_core::url::form_urlencoded;
 use deno_core::AsyncRefCell;
 use deno_core::AsyncResult;
 use deno_core::BufView;
@@ -36,10 +45,18 @@ use deno_core::OpState;
 use deno_core::RcRef;
 use deno_core::Resource;
 use deno_core::ResourceId;
+use deno_core::ResourceTable;
 use deno_core::StringOrBuffer;
+use deno_core::ToJsBuffer;
+use deno_core::WriteOutcome;
+use deno_io::fs::FileResource;
+use deno_net::io::TcpStreamResource;
+use deno_net::ops_tls::TlsStreamResource;
 use deno_net::raw::NetworkStream;
+use deno_net::raw::NetworkStreamAddress;
 use deno_websocket::ws_create_server_stream;
 use flate2::write::GzEncoder;
+use flate2::write::ZlibEncoder as ZlibWriter;
 use flate2::Compression;
 use hyper_util::rt::TokioIo;
 use hyper_v014::body::Bytes;
@@ -51,10 +68,20 @@ use hyper_v014::server::conn::Http;
 use hyper_v014::service::Service;
 use hyper_v014::Body;
 use hyper_v014::HeaderMap;
+use hyper_v014::Method;
 use hyper_v014::Request;
 use hyper_v014::Response;
+use hyper_v014::StatusCode;
+use rustls_tokio_stream::rustls::Connection as TlsConnection;
+use rustls_tokio_stream::TlsHandshake;
+use rustls_tokio_stream::TlsStream;
+use serde::Deserialize;
+use serde::Deserializer;
 use serde::Serialize;
+use socket2::SockRef;
+use socket2::TcpKeepalive;
 use std::borrow::Cow;
+use std::cell::Cell;
 use std::cell::RefCell;
 use std::cmp::min;
 use std::error::Error;
@@ -63,24 +90,45 @@ use std::io;
 use std::io::Write;
 use std::mem::replace;
 use std::mem::take;
+use std::num::NonZeroUsize;
+#[cfg(unix)]
+use std::os::fd::AsRawFd;
+#[cfg(windows)]
+use std::os::windows::io::AsRawSocket;
 use std::pin::pin;
 use std::pin::Pin;
 use std::rc::Rc;
 use std::sync::Arc;
 use std::task::Context;
 use std::task::Poll;
+use std::time::Duration;
+use std::time::SystemTime;
 use tokio::io::AsyncRead;
+use tokio::io::AsyncReadExt;
 use tokio::io::AsyncWrite;
 use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::sync::OwnedSemaphorePermit;
+use tokio::sync::Semaphore;
+use tokio::time::Instant;
+use tokio_util::io::ReaderStream;
+use tokio_util::io::StreamReader;
 
+use crate::http_next::TLS_ALPN_HTTP_11;
+use crate::http_next::TLS_ALPN_HTTP_2;
+use crate::multipart::MultipartEvent;
+use crate::multipart::MultipartParser;
 use crate::network_buffered_stream::NetworkBufferedStream;
+use crate::proxy_protocol::read_proxy_header;
 use crate::reader_stream::ExternallyAbortableReaderStream;
 use crate::reader_stream::ShutdownHandle;
 
 pub mod compressible;
 mod fly_accept_encoding;
 mod http_next;
+mod multipart;
 mod network_buffered_stream;
+mod proxy_protocol;
 mod reader_stream;
 mod request_body;
 mod request_properties;
@@ -101,13 +149,51 @@ deno_core::extension!(
   parameters = [ HTTP: HttpPropertyExtractor ],
   ops = [
     op_http_accept,
+    op_http_active_conns,
+    op_http_body_consumed,
+    op_http_conn_count,
+    op_http_conn_stats,
+    op_http_drain_body,
+    op_http_flush,
+    op_http_get_alpn,
+    op_http_get_peer_addr,
+    op_http_graceful_shutdown,
+    op_http_get_peer_certificates,
     op_http_headers,
+    op_http_multipart,
+    op_http_multipart_next,
+    op_http_read_all,
+    op_http_read_chunk,
+    op_http_read_frame,
+    op_http_read_to_file,
+    op_http_request_header,
+    op_http_request_keep_alive,
+    op_http_request_on_cancel,
+    op_http_request_query,
+    op_http_request_received_at,
+    op_http_request_target,
+    op_http_request_trailers,
+    op_http_reset,
+    op_http_response_abort,
+    op_http_response_http10,
+    op_http_serve_file,
+    op_http_set_tcp_keepalive,
     op_http_shutdown,
+    op_http_tcp_keepalive,
+    op_http_tls_sni,
+    op_http_upgrade,
+    op_http_upgrade_connect,
     op_http_upgrade_websocket,
     op_http_websocket_accept_header,
+    op_http_write_continue,
+    op_http_write_end,
+    op_http_write_full,
     op_http_write_headers,
     op_http_write_resource,
+    op_http_write_sse,
     op_http_write,
+    op_http_write_trailers,
+    op_http_write_vectored,
     http_next::op_http_close_after_finish,
     http_next::op_http_get_request_header,
     http_next::op_http_get_request_headers,
@@ -132,6 +218,13 @@ deno_core::extension!(
     http_next::op_http_cancel,
   ],
   esm = ["00_serve.ts", "01_http.js", "02_websocket.ts"],
+  options = {
+    max_concurrent_requests: Option<NonZeroUsize>,
+  },
+  state = |state, options| {
+    state.put(HttpConnCount::default());
+    state.put(HttpRequestLimit::new(options.max_concurrent_requests));
+  },
 );
 
 pub enum HttpSocketAddr {
@@ -153,45 +246,559 @@ impl From<tokio::net::unix::SocketAddr> for HttpSocketAddr {
   }
 }
 
+/// Per-connection options for the `Deno.serveHttp` server.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct HttpConnOptions {
+  /// Shut the connection down gracefully once no request has been dispatched
+  /// on it for this long. Given in milliseconds from JavaScript.
+  #[serde(deserialize_with = "deserialize_millis")]
+  pub keep_alive_timeout: Option<Duration>,
+  /// Close an HTTP/1 connection if a request head is not fully received within
+  /// this long, in milliseconds. The timer starts at the first byte of the
+  /// head, so connections idling between keep-alive requests are not
+  /// affected; use `keep_alive_timeout` to bound those.
+  #[serde(deserialize_with = "deserialize_millis")]
+  pub header_read_timeout: Option<Duration>,
+  /// Fail a request body read, and every later read of that body, if no data
+  /// arrives within this long, in milliseconds. The timer restarts with each
+  /// read, so it bounds the gap between chunks rather than the whole upload.
+  #[serde(deserialize_with = "deserialize_millis")]
+  pub body_read_timeout: Option<Duration>,
+  /// Fail a response body write and drop the connection if the client has
+  /// not taken the data within this long, in milliseconds. This stops a
+  /// client that no longer reads from holding on to a streaming response.
+  #[serde(deserialize_with = "deserialize_millis")]
+  pub body_write_timeout: Option<Duration>,
+  /// Reading more than this many bytes from a request body fails with a
+  /// `Deno.errors.RequestBodyTooLarge` error, so the handler can answer with
+  /// a 413.
+  pub max_body_size: Option<u64>,
+  /// Decode request bodies sent with a `Content-Encoding` of `gzip`,
+  /// `deflate` or `br`, so reads return the decoded bytes. Those requests
+  /// lose their `Content-Encoding` and `Content-Length` headers, and their
+  /// trailers. `maxBodySize` limits the decoded size. Bodies with any other
+  /// coding, or several, are left as they are.
+  pub decompress_request_body: bool,
+  /// Return request bodies of up to this many bytes together with the request
+  /// when they have fully arrived by then, so that handlers need no further
+  /// op call to read them. Other bodies are read from the request resource
+  /// as usual. Disabled by default.
+  pub inline_body_size: Option<usize>,
+  /// Hold back small chunks of a streamed response body until this many
+  /// bytes have been written, the body is flushed with `op_http_flush` or it
+  /// ends, and send them as one. This saves a body frame and a trip through
+  /// the connection per chunk for producers that write many small chunks.
+  /// Chunks are sent as they are written by default. Compressed bodies are
+  /// not buffered here.
+  pub write_buffer_size: Option<usize>,
+  /// Answer requests with more header fields than this with a 431 before
+  /// they are dispatched, so no request resource is created for them. Hyper
+  /// always rejects HTTP/1 requests with more than 100 header fields.
+  pub max_headers: Option<usize>,
+  /// Largest request head accepted, in bytes. HTTP/1 heads over the limit are
+  /// answered with a 431 by hyper itself; on HTTP/2 it is advertised as the
+  /// maximum header list size. Must be at least 8192.
+  ///
+  /// Hyper uses the same limit for its HTTP/1 write buffer, so this also
+  /// caps how many response bytes are queued before they are written to the
+  /// socket. It defaults to about 400KB.
+  pub max_header_size: Option<usize>,
+  /// Whether HTTP/1 responses are written with vectored writes (`true`) or
+  /// copied into a single buffer first (`false`). By default hyper picks
+  /// based on whether the transport supports vectored writes well.
+  pub http1_writev: Option<bool>,
+  /// Answer HTTP/1 requests carrying both `Content-Length` and
+  /// `Transfer-Encoding` with a 400 before they are dispatched. Hyper frames
+  /// such bodies as chunked, but a proxy in front may have used the length,
+  /// which is how requests get smuggled. Hyper already drops a
+  /// `Content-Length` that follows `Transfer-Encoding`, so only the opposite
+  /// order is caught here.
+  pub strict_framing: bool,
+  /// Brotli quality level (0-11) for compressed responses. Defaults to 6 for
+  /// buffered bodies and to the fastest level for streamed bodies.
+  pub brotli_quality: Option<u32>,
+  /// Brotli window size as a power of two (`lgwin`, 10-24). Defaults to 22.
+  pub brotli_window: Option<u32>,
+  /// Return every `Cookie` request header as its own entry instead of joining
+  /// them into a single header. Proxies that treat `Cookie` as opaque can set
+  /// this to forward the original headers byte for byte.
+  pub separate_cookies: bool,
+  /// Serve plaintext connections as HTTP/2 only, for clients that start with
+  /// the HTTP/2 connection preface instead of negotiating it (h2c with prior
+  /// knowledge). HTTP/1 requests are rejected. TLS connections are unaffected
+  /// and keep using ALPN.
+  pub http2_prior_knowledge: bool,
+  /// HTTP/2 flow control windows, in bytes. They default to 1MB each, and
+  /// HTTP/1 connections ignore them, like the other HTTP/2 settings below.
+  pub http2_initial_stream_window_size: Option<u32>,
+  pub http2_initial_connection_window_size: Option<u32>,
+  /// Largest HTTP/2 frame payload the client may send, between 16KB (the
+  /// default) and 16MB - 1.
+  pub http2_max_frame_size: Option<u32>,
+  /// Number of HTTP/2 streams the client may have open at once. Unlimited by
+  /// default.
+  pub http2_max_concurrent_streams: Option<u32>,
+  /// Advertise the extended CONNECT protocol (RFC 8441) in the HTTP/2
+  /// settings, so clients can open tunnels for other protocols with a
+  /// `:protocol` pseudo-header. See `op_http_upgrade_connect`.
+  pub http2_enable_connect_protocol: bool,
+  /// Expect plaintext connections to start with a PROXY protocol v1 or v2
+  /// header, and report the client address it carries as the peer address.
+  /// Connections without a valid header are closed. TLS connections are not
+  /// supported, as the header would have to be read before the handshake.
+  pub proxy_protocol: bool,
+  /// Build request URLs with the scheme a proxy reports in the `Forwarded`
+  /// or `X-Forwarded-Proto` request header, when it is `http` or `https`.
+  /// Only enable this behind a proxy that sets those headers itself, as
+  /// clients can send them too.
+  pub trust_forwarded_proto: bool,
+  /// Socket options applied to plain TCP connections before they are served.
+  /// They are ignored for other transports: the socket under a TLS stream is
+  /// not reachable from here, so TLS listeners have to be tuned beforehand.
+  pub tcp_nodelay: Option<bool>,
+  /// Idle time before TCP keepalive probes are sent, in milliseconds.
+  #[serde(deserialize_with = "deserialize_millis")]
+  pub tcp_keepalive: Option<Duration>,
+  pub send_buffer_size: Option<usize>,
+  pub recv_buffer_size: Option<usize>,
+  /// Headers added to every response that does not set them itself, eg: a
+  /// `Server` banner. Given as name-value pairs from JavaScript. A default
+  /// `Date` header replaces the one hyper adds, but cannot remove it.
+  #[serde(deserialize_with = "deserialize_headers")]
+  pub default_headers: HeaderMap,
+  /// Answer `GET` and `HEAD` requests for this path with a static response,
+  /// without dispatching them to JS, eg: for load balancer health checks.
+  /// Requests are still only read while an accept op is pending.
+  pub health_check: Option<HttpHealthCheck>,
+  /// Changes applied to the headers of every request, in order, before the
+  /// request is accepted. Everything that reads request headers sees the
+  /// result, including `trustForwardedProto`.
+  pub header_transforms: Vec<HttpHeaderTransform>,
+  /// Answer requests beyond this rate with `429 Too Many Requests`, without
+  /// dispatching them to JS. Each connection has its own limit. Health check
+  /// requests are not counted.
+  pub rate_limit: Option<HttpRateLimit>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HttpRateLimit {
+  pub requests_per_second: f64,
+  /// How many requests can arrive at once after the connection was idle.
+  /// Defaults to one second's worth, and at least 1.
+  #[serde(default)]
+  pub burst: Option<u32>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum HttpHeaderTransform {
+  /// Moves all values of the `from` header to the `to` header, after any
+  /// values it already has.
+  Rename {
+    #[serde(deserialize_with = "deserialize_header_name")]
+    from: HeaderName,
+    #[serde(deserialize_with = "deserialize_header_name")]
+    to: HeaderName,
+  },
+  /// Adds the header if the request does not have it.
+  Default {
+    #[serde(deserialize_with = "deserialize_header_name")]
+    name: HeaderName,
+    #[serde(deserialize_with = "deserialize_header_value")]
+    value: HeaderValue,
+  },
+  /// Removes all values of the header.
+  Strip {
+    #[serde(deserialize_with = "deserialize_header_name")]
+    name: HeaderName,
+  },
+}
+
+fn transform_headers(
+  headers: &mut HeaderMap,
+  transforms: &[HttpHeaderTransform],
+) {
+  for transform in transforms {
+    match transform {
+      HttpHeaderTransform::Rename { from, to } => {
+        let values = headers.get_all(from).iter().cloned().collect::<Vec<_>>();
+        headers.remove(from);
+        for value in values {
+          headers.append(to, value);
+        }
+      }
+      HttpHeaderTransform::Default { name, value } => {
+        if !headers.contains_key(name) {
+          headers.insert(name, value.clone());
+        }
+      }
+      HttpHeaderTransform::Strip { name } => {
+        headers.remove(name);
+      }
+    }
+  }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HttpHealthCheck {
+  /// Matched against the request path exactly, without the query.
+  pub path: String,
+  /// Defaults to 200.
+  #[serde(default)]
+  pub status: Option<u16>,
+  #[serde(default)]
+  pub body: String,
+}
+
+fn deserialize_headers<'de, D>(deserializer: D) -> Result<HeaderMap, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  let headers = Vec::<(String, String)>::deserialize(deserializer)?;
+  let mut hmap = HeaderMap::with_capacity(headers.len());
+  for (k, v) in headers {
+    let k = HeaderName::try_from(k).map_err(serde::de::Error::custom)?;
+    let v = HeaderValue::try_from(v).map_err(serde::de::Error::custom)?;
+    hmap.append(k, v);
+  }
+  Ok(hmap)
+}
+
+fn deserialize_header_name<'de, D>(
+  deserializer: D,
+) -> Result<HeaderName, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  let name = String::deserialize(deserializer)?;
+  HeaderName::try_from(name).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_header_value<'de, D>(
+  deserializer: D,
+) -> Result<HeaderValue, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  let value = String::deserialize(deserializer)?;
+  HeaderValue::try_from(value).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_millis<'de, D>(
+  deserializer: D,
+) -> Result<Option<Duration>, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  let millis = Option::<u64>::deserialize(deserializer)?;
+  Ok(millis.map(Duration::from_millis))
+}
+
+/// TCP keepalive settings for `op_http_set_tcp_keepalive`. Unset fields keep
+/// the system defaults.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct HttpTcpKeepalive {
+  /// Idle time before the first probe, in milliseconds.
+  #[serde(deserialize_with = "deserialize_millis")]
+  idle: Option<Duration>,
+  /// Time between probes, in milliseconds.
+  #[serde(deserialize_with = "deserialize_millis")]
+  interval: Option<Duration>,
+  /// Unanswered probes after which the connection is dropped. Not supported
+  /// on Windows, where it is ignored.
+  count: Option<u32>,
+}
+
+#[cfg(unix)]
+type RawTcpSocket = std::os::fd::RawFd;
+#[cfg(windows)]
+type RawTcpSocket = std::os::windows::io::RawSocket;
+
+/// Forgets the socket of a connection when hyper drops its transport, so
+/// that the raw handle is never used after it is closed.
+struct TcpSocketGuard(Rc<Cell<Option<RawTcpSocket>>>);
+
+impl Drop for TcpSocketGuard {
+  fn drop(&mut self) {
+    self.0.set(None);
+  }
+}
+
+/// Counters of a `Deno.serveHttp` connection. Byte counts cover request and
+/// response bodies only, before any response compression. Response bytes are
+/// counted once a write succeeded, not while they sit in the write buffer.
+#[derive(Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct HttpConnStats {
+  requests: Cell<u64>,
+  bytes_read: Cell<u64>,
+  bytes_written: Cell<u64>,
+}
+
+impl HttpConnStats {
+  fn add(counter: &Cell<u64>, n: usize) {
+    counter.set(counter.get() + n as u64);
+  }
+}
+
+/// The number of `Deno.serveHttp` connections that are still being served.
+#[derive(Clone, Default)]
+struct HttpConnCount(Rc<Cell<usize>>);
+
+impl HttpConnCount {
+  fn acquire(&self) -> HttpConnCountGuard {
+    self.0.set(self.0.get() + 1);
+    HttpConnCountGuard(self.0.clone())
+  }
+}
+
+/// Counts a connection until it is dropped. It is owned by the connection
+/// task, so the count goes down however the connection ends.
+struct HttpConnCountGuard(Rc<Cell<usize>>);
+
+impl Drop for HttpConnCountGuard {
+  fn drop(&mut self) {
+    self.0.set(self.0.get() - 1);
+  }
+}
+
+/// Caps the `Deno.serveHttp` requests in flight across all connections, so
+/// they are accepted only as fast as earlier responses complete. Set with the
+/// `max_concurrent_requests` extension option; unlimited by default. Limits
+/// above `Semaphore::MAX_PERMITS` are clamped to it.
+#[derive(Clone)]
+struct HttpRequestLimit(Option<Arc<Semaphore>>);
+
+impl HttpRequestLimit {
+  fn new(max_concurrent_requests: Option<NonZeroUsize>) -> Self {
+    Self(max_concurrent_requests.map(|max| {
+      Arc::new(Semaphore::new(max.get().min(Semaphore::MAX_PERMITS)))
+    }))
+  }
+
+  /// Waits for a request to be allowed. Nothing is held without a limit.
+  async fn acquire(&self) -> Option<OwnedSemaphorePermit> {
+    let semaphore = self.0.clone()?;
+    // The semaphore is never closed.
+    semaphore.acquire_owned().await.ok()
+  }
+}
+
+/// The buffer size deno_net gives TLS streams.
+const TLS_BUFFER_SIZE: Option<NonZeroUsize> = NonZeroUsize::new(65536);
+
+/// What the TLS handshake of a connection established.
+#[derive(Clone)]
+struct HttpTlsInfo {
+  handshake: TlsHandshake,
+  // The DER certificate chain the client presented, leaf first.
+  peer_certificates: Rc<Vec<Vec<u8>>>,
+}
+
+/// Reads the certificate chain the client presented from the rustls
+/// connection of a TLS stream whose handshake is complete, which the stream
+/// itself does not expose, and wraps the connection in a new stream.
+async fn peer_certificates(
+  tls: TlsStream,
+) -> io::Result<(TlsStream, Vec<Vec<u8>>)> {
+  let (tcp, connection) = tls.into_inner().await?;
+  match connection {
+    TlsConnection::Server(connection) => {
+      let certs = connection
+        .peer_certificates()
+        .unwrap_or_default()
+        .iter()
+        .map(|cert| cert.0.clone())
+        .collect();
+      let tls =
+        TlsStream::new_server_side_from(tcp, connection, TLS_BUFFER_SIZE);
+      Ok((tls, certs))
+    }
+    // The peer of a client-side stream is the server.
+    TlsConnection::Client(connection) => {
+      let tls =
+        TlsStream::new_client_side_from(tcp, connection, TLS_BUFFER_SIZE);
+      Ok((tls, vec![]))
+    }
+  }
+}
+
 struct HttpConnResource {
   addr: HttpSocketAddr,
+  // Replaced by the client address from a PROXY protocol header, if any.
+  peer_addr: Rc<RefCell<Option<NetworkStreamAddress>>>,
   scheme: &'static str,
+  options: HttpConnOptions,
   acceptors_tx: mpsc::UnboundedSender<HttpAcceptor>,
   closed_fut: Shared<RemoteHandle<Result<(), Arc<hyper_v014::Error>>>>,
+  tls_info_fut: Shared<oneshot::Receiver<Option<HttpTlsInfo>>>,
+  // Read and write stream resources of accepted requests that may still be
+  // open. They are closed if the connection fails.
+  stream_rids: RefCell<Vec<ResourceId>>,
+  stats: HttpConnStats,
   cancel_handle: Rc<CancelHandle>, // Closes gracefully and cancels accept ops.
+  graceful_shutdown_handle: Rc<CancelHandle>, // Only closes gracefully.
+  abort_handle: Rc<CancelHandle>,
+  // The socket of a plain TCP connection, while hyper still owns it.
+  tcp_socket: Rc<Cell<Option<RawTcpSocket>>>,
 }
 
 impl HttpConnResource {
-  fn new<S>(io: S, scheme: &'static str, addr: HttpSocketAddr) -> Self
-  where
-    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
-  {
+  fn new(
+    mut io: NetworkStream,
+    scheme: &'static str,
+    addr: HttpSocketAddr,
+    options: HttpConnOptions,
+    count_guard: HttpConnCountGuard,
+  ) -> Self {
+    let peer_addr = Rc::new(RefCell::new(io.peer_address().ok()));
     let (acceptors_tx, acceptors_rx) = mpsc::unbounded::<HttpAcceptor>();
-    let service = HttpService::new(acceptors_rx);
-
-    let conn_fut = Http::new()
-      .with_executor(LocalExecutor)
-      .serve_connection(io, service)
-      .with_upgrades();
+    let last_request = Rc::new(Cell::new(Instant::now()));
+    let service =
+      HttpService::new(acceptors_rx, last_request.clone(), &options);
 
     // When the cancel handle is used, the connection shuts down gracefully.
     // No new HTTP streams will be accepted, but existing streams will be able
     // to continue operating and eventually shut down cleanly.
     let cancel_handle = CancelHandle::new_rc();
-    let shutdown_fut = never().or_cancel(&cancel_handle).fuse();
+    let handshake_cancel_handle = cancel_handle.clone();
+    // An idle keep-alive timeout shuts the connection down the same way.
+    let idle_fut = match options.keep_alive_timeout {
+      Some(timeout) => keep_alive_timeout(last_request, timeout).boxed_local(),
+      None => pending().boxed_local(),
+    };
+    // `op_http_graceful_shutdown` shuts down without cancelling accept ops;
+    // those resolve with `None` once the in-flight requests have drained.
+    let graceful_shutdown_handle = CancelHandle::new_rc();
+    // Closes the connection without waiting for in-flight responses.
+    let abort_handle = CancelHandle::new_rc();
+    let task_abort_handle = abort_handle.clone();
+    let shutdown_fut = idle_fut
+      .or_cancel(&cancel_handle)
+      .or_cancel(&graceful_shutdown_handle)
+      .fuse();
+
+    let (tls_info_tx, tls_info_rx) = oneshot::channel();
+    let header_read_timeout = options.header_read_timeout;
+    let max_header_size = options.max_header_size;
+    let http1_writev = options.http1_writev;
+    let stream_window_size = options.http2_initial_stream_window_size;
+    let connection_window_size = options.http2_initial_connection_window_size;
+    let max_frame_size = options.http2_max_frame_size;
+    let max_concurrent_streams = options.http2_max_concurrent_streams;
+    let enable_connect_protocol = options.http2_enable_connect_protocol;
+    let http2_prior_knowledge =
+      options.http2_prior_knowledge && !matches!(io, NetworkStream::Tls(_));
+    let proxy_protocol =
+      options.proxy_protocol && !matches!(io, NetworkStream::Tls(_));
+    let proxied_peer_addr = peer_addr.clone();
+    let tcp_socket = Rc::new(Cell::new(match &io {
+      #[cfg(unix)]
+      NetworkStream::Tcp(stream) => Some(stream.as_raw_fd()),
+      #[cfg(windows)]
+      NetworkStream::Tcp(stream) => Some(stream.as_raw_socket()),
+      _ => None,
+    }));
+    let tcp_socket_guard = TcpSocketGuard(tcp_socket.clone());
 
     // A local task that polls the hyper connection future to completion.
     let task_fut = async move {
-      let conn_fut = pin!(conn_fut);
-      let shutdown_fut = pin!(shutdown_fut);
-      let result = match select(conn_fut, shutdown_fut).await {
-        Either::Left((result, _)) => result,
-        Either::Right((_, mut conn_fut)) => {
-          conn_fut.as_mut().graceful_shutdown();
-          conn_fut.await
+      let _count_guard = count_guard;
+      let _tcp_socket_guard = tcp_socket_guard;
+      if proxy_protocol {
+        let header = read_proxy_header(&mut io)
+          .or_cancel(handshake_cancel_handle.clone())
+          .await;
+        match header {
+          Ok(Ok(Some(addr))) => {
+            *proxied_peer_addr.borrow_mut() = Some(addr.into());
+          }
+          Ok(Ok(None)) => {}
+          // Close the connection without serving it.
+          _ => return Ok(()),
+        }
+      }
+
+      // If the client specifically negotiates a protocol via ALPN, we will use
+      // it. If not, hyper auto-detects the protocol based on the prefix bytes.
+      // A failed handshake is reported by the connection future below.
+      let handshake = match &mut io {
+        NetworkStream::Tls(tls) => tls
+          .handshake()
+          .or_cancel(handshake_cancel_handle)
+          .await
+          .ok()
+          .and_then(|handshake| handshake.ok()),
+        _ => None,
+      };
+      let alpn = handshake
+        .as_ref()
+        .and_then(|handshake| handshake.alpn.clone());
+      let mut certs = vec![];
+      if handshake.as_ref().is_some_and(|h| h.has_peer_certificates) {
+        let NetworkStream::Tls(tls) = io else {
+          unreachable!();
+        };
+        // Closes the connection if the stream failed in the meantime.
+        let Ok((tls, peer_certificates)) = peer_certificates(tls).await else {
+          return Ok(());
+        };
+        io = NetworkStream::Tls(tls);
+        certs = peer_certificates;
+      }
+      let peer_certificates = Rc::new(certs);
+      let _ = tls_info_tx.send(handshake.map(|handshake| HttpTlsInfo {
+        handshake,
+        peer_certificates,
+      }));
+
+      let mut http = Http::new().with_executor(LocalExecutor);
+      if Some(TLS_ALPN_HTTP_2) == alpn.as_deref() || http2_prior_knowledge {
+        http.http2_only(true);
+      } else if Some(TLS_ALPN_HTTP_11) == alpn.as_deref() {
+        http.http1_only(true);
+      }
+      if let Some(timeout) = header_read_timeout {
+        http.http1_header_read_timeout(timeout);
+      }
+      if let Some(writev) = http1_writev {
+        http.http1_writev(writev);
+      }
+      if let Some(size) = max_header_size {
+        http.max_buf_size(size);
+        http.http2_max_header_list_size(size.try_into().unwrap_or(u32::MAX));
+      }
+      if enable_connect_protocol {
+        http.http2_enable_connect_protocol();
+      }
+      http
+        .http2_initial_stream_window_size(stream_window_size)
+        .http2_initial_connection_window_size(connection_window_size)
+        .http2_max_frame_size(max_frame_size)
+        .http2_max_concurrent_streams(max_concurrent_streams);
+      let conn_fut = http.serve_connection(io, service).with_upgrades();
+
+      let serve_fut = async {
+        let conn_fut = pin!(conn_fut);
+        let shutdown_fut = pin!(shutdown_fut);
+        match select(conn_fut, shutdown_fut).await {
+          Either::Left((result, _)) => result,
+          Either::Right((_, mut conn_fut)) => {
+            conn_fut.as_mut().graceful_shutdown();
+            conn_fut.await
+          }
         }
       };
-      filter_enotconn(result).map_err(Arc::from)
+      // Aborting drops the connection, along with any response in progress.
+      let result = serve_fut
+        .or_cancel(task_abort_handle)
+        .await
+        .unwrap_or(Ok(()));
+      filter_disconnect_errors(result).map_err(Arc::from)
     };
     let (task_fut, closed_fut) = task_fut.remote_handle();
     let closed_fut = closed_fut.shared();
@@ -199,13 +806,48 @@ impl HttpConnResource {
 
     Self {
       addr,
+      peer_addr,
       scheme,
+      options,
       acceptors_tx,
       closed_fut,
+      tls_info_fut: tls_info_rx.shared(),
+      stream_rids: Default::default(),
+      stats: Default::default(),
       cancel_handle,
+      graceful_shutdown_handle,
+      abort_handle,
+      tcp_socket,
+    }
+  }
+
+  fn peer_ip_addr(&self) -> Option<(String, u16)> {
+    match *self.peer_addr.borrow() {
+      Some(NetworkStreamAddress::Ip(addr)) => {
+        Some((addr.ip().to_string(), addr.port()))
+      }
+      _ => None,
     }
   }
 
+  /// Calls `f` with the socket of a plain TCP connection, or returns `None`
+  /// for other transports and once the connection is closed or upgraded.
+  fn with_tcp_socket<R>(
+    &self,
+    f: impl FnOnce(SockRef) -> io::Result<R>,
+  ) -> Option<io::Result<R>> {
+    let socket = self.tcp_socket.get()?;
+    // SAFETY: the guard in the connection task clears `tcp_socket` when the
+    // transport is dropped, which happens on this thread, so the handle is
+    // still open here.
+    #[cfg(unix)]
+    let socket = unsafe { std::os::fd::BorrowedFd::borrow_raw(socket) };
+    #[cfg(windows)]
+    let socket =
+      unsafe { std::os::windows::io::BorrowedSocket::borrow_raw(socket) };
+    Some(f(SockRef::from(&socket)))
+  }
+
   // Accepts a new incoming HTTP request.
   async fn accept(
     self: &Rc<Self>,
@@ -215,6 +857,7 @@ impl HttpConnResource {
       HttpStreamWriteResource,
       String,
       String,
+      &'static str,
     )>,
     AnyError,
   > {
@@ -225,26 +868,39 @@ impl HttpConnResource {
       let acceptor = HttpAcceptor::new(request_tx, response_rx);
       self.acceptors_tx.unbounded_send(acceptor).ok()?;
 
-      let request = request_rx.await.ok()?;
-      let accept_encoding = {
-        let encodings =
-          fly_accept_encoding::encodings_iter_http_02(request.headers())
-            .filter(|r| {
-              matches!(r, Ok((Some(Encoding::Brotli | Encoding::Gzip), _)))
-            });
-
-        fly_accept_encoding::preferred(encodings)
-          .ok()
-          .flatten()
-          .unwrap_or(Encoding::Identity)
-      };
+      let mut request = request_rx.await.ok()?;
+      transform_headers(request.headers_mut(), &self.options.header_transforms);
+      if self.options.decompress_request_body {
+        decompress_request_body(&mut request);
+      }
+      let accept_encoding = fly_accept_encoding::negotiate(
+        fly_accept_encoding::encodings_iter_http_02(request.headers()),
+        &[Encoding::Brotli, Encoding::Gzip, Encoding::Deflate],
+      )
+      .unwrap_or(Encoding::Identity);
 
+      HttpConnStats::add(&self.stats.requests, 1);
+      let is_head = request.method() == Method::HEAD;
+      let is_http2 = request.version() == hyper_v014::Version::HTTP_2;
       let method = request.method().to_string();
-      let url = req_url(&request, self.scheme, &self.addr);
-      let read_stream = HttpStreamReadResource::new(self, request);
-      let write_stream =
-        HttpStreamWriteResource::new(self, response_tx, accept_encoding);
-      Some((read_stream, write_stream, method, url))
+      let scheme = match self.options.trust_forwarded_proto {
+        true => forwarded_scheme(request.headers()).unwrap_or(self.scheme),
+        false => self.scheme,
+      };
+      let url = req_url(&request, scheme, &self.addr);
+      let version = req_version(request.version());
+      let upgrade = Rc::new(HttpUpgradeState::default());
+      let read_stream =
+        HttpStreamReadResource::new(self, request, upgrade.clone());
+      let write_stream = HttpStreamWriteResource::new(
+        self,
+        response_tx,
+        accept_encoding,
+        is_head,
+        is_http2,
+        upgrade,
+      );
+      Some((read_stream, write_stream, method, url, version))
     };
 
     async {
@@ -258,9 +914,47 @@ impl HttpConnResource {
     .await
   }
 
+  /// Waits for the TLS handshake to complete. Resolves to `None` for plaintext
+  /// connections and for handshakes that failed.
+  async fn tls_info(&self) -> Option<HttpTlsInfo> {
+    self.tls_info_fut.clone().await.ok().flatten()
+  }
+
   /// A future that completes when this HTTP connection is closed or errors.
   async fn closed(&self) -> Result<(), AnyError> {
-    self.closed_fut.clone().map_err(AnyError::from).await
+    self
+      .closed_fut
+      .clone()
+      .map_err(|err| hyper_error(&err))
+      .await
+  }
+
+  /// Remembers the stream resources of an accepted request, forgetting those
+  /// of earlier requests that have been closed since.
+  fn track_streams(
+    &self,
+    resource_table: &ResourceTable,
+    rids: [ResourceId; 2],
+  ) {
+    let mut stream_rids = self.stream_rids.borrow_mut();
+    stream_rids.retain(|rid| resource_table.has(*rid));
+    stream_rids.extend(rids);
+  }
+
+  /// Closes the stream resources of requests that are still in flight.
+  fn close_streams(&self, state: &RefCell<OpState>) {
+    let resources = {
+      let resource_table = &mut state.borrow_mut().resource_table;
+      self
+        .stream_rids
+        .take()
+        .into_iter()
+        .filter_map(|rid| resource_table.take_any(rid).ok())
+        .collect::<Vec<_>>()
+    };
+    for resource in resources {
+      resource.close();
+    }
   }
 }
 
@@ -274,32 +968,189 @@ impl Resource for HttpConnResource {
   }
 }
 
+/// Resolves once no request has been dispatched on the connection for
+/// `timeout`. The deadline moves forward every time `HttpService::call` runs.
+async fn keep_alive_timeout(
+  last_request: Rc<Cell<Instant>>,
+  timeout: Duration,
+) {
+  loop {
+    let deadline = last_request.get() + timeout;
+    if deadline <= Instant::now() {
+      break;
+    }
+    tokio::time::sleep_until(deadline).await;
+  }
+}
+
 /// Creates a new HttpConn resource which uses `io` as its transport.
+///
+/// TLS connections that negotiated `h2` or `http/1.1` via ALPN are served
+/// with that protocol only; all other connections auto-detect HTTP/2 from the
+/// connection preface.
 pub fn http_create_conn_resource<S, A>(
   state: &mut OpState,
   io: S,
   addr: A,
   scheme: &'static str,
+  options: HttpConnOptions,
 ) -> Result<ResourceId, AnyError>
 where
-  S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+  S: Into<NetworkStream>,
   A: Into<HttpSocketAddr>,
 {
-  let conn = HttpConnResource::new(io, scheme, addr.into());
+  if let Some(status) = options.health_check.as_ref().and_then(|h| h.status) {
+    StatusCode::from_u16(status).map_err(|_| {
+      type_error(format!("Invalid health check status {status}"))
+    })?;
+  }
+  if let Some(rate_limit) = &options.rate_limit {
+    let rate = rate_limit.requests_per_second;
+    if !(rate.is_finite() && rate > 0.0) {
+      return Err(type_error(format!("Invalid requests per second {rate}")));
+    }
+  }
+  if options.brotli_quality.is_some_and(|quality| quality > 11) {
+    return Err(type_error("brotliQuality must be between 0 and 11"));
+  }
+  if options
+    .brotli_window
+    .is_some_and(|window| !(10..=24).contains(&window))
+  {
+    return Err(type_error("brotliWindow must be between 10 and 24"));
+  }
+  if options.max_header_size.is_some_and(|size| size < 8192) {
+    return Err(type_error("maxHeaderSize must be at least 8192"));
+  }
+  // RFC 9113, section 6.5.2.
+  let window_sizes = [
+    options.http2_initial_stream_window_size,
+    options.http2_initial_connection_window_size,
+  ];
+  if window_sizes
+    .iter()
+    .flatten()
+    .any(|size| *size > (1 << 31) - 1)
+  {
+    return Err(type_error("HTTP/2 window sizes must be at most 2^31 - 1"));
+  }
+  if options
+    .http2_max_frame_size
+    .is_some_and(|size| !(1 << 14..1 << 24).contains(&size))
+  {
+    return Err(type_error(
+      "http2MaxFrameSize must be between 16384 and 16777215",
+    ));
+  }
+  let io = io.into();
+  if let NetworkStream::Tcp(stream) = &io {
+    set_tcp_options(stream, &options)?;
+  }
+  let count_guard = state.borrow::<HttpConnCount>().acquire();
+  let conn =
+    HttpConnResource::new(io, scheme, addr.into(), options, count_guard);
   let rid = state.resource_table.add(conn);
   Ok(rid)
 }
 
+fn set_tcp_options(
+  stream: &TcpStream,
+  options: &HttpConnOptions,
+) -> Result<(), AnyError> {
+  let socket = SockRef::from(stream);
+  if let Some(nodelay) = options.tcp_nodelay {
+    socket.set_nodelay(nodelay)?;
+  }
+  if let Some(time) = options.tcp_keepalive {
+    socket.set_tcp_keepalive(&TcpKeepalive::new().with_time(time))?;
+  }
+  if let Some(size) = options.send_buffer_size {
+    socket.set_send_buffer_size(size)?;
+  }
+  if let Some(size) = options.recv_buffer_size {
+    socket.set_recv_buffer_size(size)?;
+  }
+  Ok(())
+}
+
 /// An object that implements the `hyper::Service` trait, through which Hyper
 /// delivers incoming HTTP requests.
 struct HttpService {
   acceptors_rx: Peekable<mpsc::UnboundedReceiver<HttpAcceptor>>,
+  last_request: Rc<Cell<Instant>>,
+  max_headers: Option<usize>,
+  strict_framing: bool,
+  health_check: Option<(String, StatusCode, Bytes)>,
+  rate_limit: Option<TokenBucket>,
 }
 
 impl HttpService {
-  fn new(acceptors_rx: mpsc::UnboundedReceiver<HttpAcceptor>) -> Self {
+  fn new(
+    acceptors_rx: mpsc::UnboundedReceiver<HttpAcceptor>,
+    last_request: Rc<Cell<Instant>>,
+    options: &HttpConnOptions,
+  ) -> Self {
     let acceptors_rx = acceptors_rx.peekable();
-    Self { acceptors_rx }
+    Self {
+      acceptors_rx,
+      last_request,
+      max_headers: options.max_headers,
+      strict_framing: options.strict_framing,
+      health_check: options.health_check.as_ref().map(|health_check| {
+        let status = health_check.status.unwrap_or(200);
+        (
+          health_check.path.clone(),
+          // Validated in `http_create_conn_resource`.
+          StatusCode::from_u16(status).unwrap_or(StatusCode::OK),
+          Bytes::from(health_check.body.clone()),
+        )
+      }),
+      rate_limit: options.rate_limit.as_ref().map(|rate_limit| {
+        let rate = rate_limit.requests_per_second;
+        let burst = rate_limit.burst.map_or(rate.ceil(), f64::from);
+        TokenBucket::new(rate, burst.max(1.0))
+      }),
+    }
+  }
+
+  /// Counts a request against the rate limit, returning whether it is over.
+  fn rate_limited(&mut self) -> bool {
+    self
+      .rate_limit
+      .as_mut()
+      .is_some_and(|bucket| !bucket.try_take(Instant::now()))
+  }
+
+  /// Returns the static response for a health check request.
+  fn health_check(&self, request: &Request<Body>) -> Option<Response<Body>> {
+    let (path, status, body) = self.health_check.as_ref()?;
+    let method = request.method();
+    if request.uri().path() != path
+      || !(method == Method::GET || method == Method::HEAD)
+    {
+      return None;
+    }
+    let mut response = Response::new(Body::from(body.clone()));
+    *response.status_mut() = *status;
+    Some(response)
+  }
+
+  /// Returns the status to answer `request` with instead of dispatching it.
+  fn rejection(&self, request: &Request<Body>) -> Option<StatusCode> {
+    let headers = request.headers();
+    if self
+      .max_headers
+      .is_some_and(|max_headers| headers.len() > max_headers)
+    {
+      return Some(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+    }
+    if self.strict_framing
+      && headers.contains_key(hyper_v014::header::CONTENT_LENGTH)
+      && headers.contains_key(hyper_v014::header::TRANSFER_ENCODING)
+    {
+      return Some(StatusCode::BAD_REQUEST);
+    }
+    None
   }
 }
 
@@ -319,12 +1170,72 @@ impl Service<Request<Body>> for HttpService {
     Poll::Ready(result)
   }
 
-  fn call(&mut self, request: Request<Body>) -> Self::Future {
+  fn call(&mut self, mut request: Request<Body>) -> Self::Future {
+    let response = match self.rejection(&request) {
+      Some(status) => {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = status;
+        Some(response)
+      }
+      None => self.health_check(&request).or_else(|| {
+        self.rate_limited().then(|| {
+          let mut response = Response::new(Body::empty());
+          *response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+          response
+        })
+      }),
+    };
+    if let Some(response) = response {
+      // Answered without taking an acceptor, so the request never reaches JS.
+      let (response_tx, response_rx) = oneshot::channel();
+      let _ = response_tx.send(response);
+      return response_rx;
+    }
     let acceptor = self.acceptors_rx.next().now_or_never().flatten().unwrap();
+    self.last_request.set(Instant::now());
+    request
+      .extensions_mut()
+      .insert(RequestReceivedAt(SystemTime::now()));
     acceptor.call(request)
   }
 }
 
+/// Allows `rate` requests per second on average, and up to `burst` at once.
+struct TokenBucket {
+  rate: f64,
+  burst: f64,
+  tokens: f64,
+  refilled_at: Instant,
+}
+
+impl TokenBucket {
+  fn new(rate: f64, burst: f64) -> Self {
+    Self {
+      rate,
+      burst,
+      tokens: burst,
+      refilled_at: Instant::now(),
+    }
+  }
+
+  fn try_take(&mut self, now: Instant) -> bool {
+    let elapsed = now.saturating_duration_since(self.refilled_at);
+    self.tokens =
+      (self.tokens + elapsed.as_secs_f64() * self.rate).min(self.burst);
+    self.refilled_at = now;
+    if self.tokens < 1.0 {
+      return false;
+    }
+    self.tokens -= 1.0;
+    true
+  }
+}
+
+/// The time hyper finished parsing the request head, stored as a request
+/// extension.
+#[derive(Clone, Copy)]
+struct RequestReceivedAt(SystemTime);
+
 /// A pair of one-shot channels which first transfer a HTTP request from the
 /// Hyper service to the HttpConn resource, and then take the Response back to
 /// the service.
@@ -357,27 +1268,148 @@ impl HttpAcceptor {
 }
 
 pub struct HttpStreamReadResource {
-  _conn: Rc<HttpConnResource>,
+  conn: Rc<HttpConnResource>,
   pub rd: AsyncRefCell<HttpRequestReader>,
   cancel_handle: CancelHandle,
   size: SizeHint,
+  bytes_read: Cell<u64>,
+  received_at: SystemTime,
+  uri: hyper_v014::Uri,
+  target: String,
+  keep_alive: bool,
+  upgrade: Rc<HttpUpgradeState>,
 }
 
 pub struct HttpStreamWriteResource {
   conn: Rc<HttpConnResource>,
   wr: AsyncRefCell<HttpResponseWriter>,
   accept_encoding: Encoding,
+  // Responses to HEAD requests are sent without a body. Body chunks written
+  // by the handler are discarded.
+  is_head: bool,
+  is_http2: bool,
+  // Set by `op_http_response_http10`.
+  http10: Cell<bool>,
+  upgrade: Rc<HttpUpgradeState>,
+  // Counts against `HttpRequestLimit` until the response stream is closed.
+  _request_permit: Option<OwnedSemaphorePermit>,
+}
+
+/// What the read and write streams of a request share about upgrading it.
+#[derive(Default)]
+struct HttpUpgradeState {
+  // Whether hyper can still upgrade the connection: the request asked for it
+  // and its head, which holds the pending upgrade, has not been dropped to
+  // read the body.
+  pending: Cell<bool>,
+  // The subprotocol chosen by a `101 Switching Protocols` response.
+  websocket_protocol: RefCell<Option<String>>,
 }
 
 impl HttpStreamReadResource {
-  fn new(conn: &Rc<HttpConnResource>, request: Request<Body>) -> Self {
+  fn new(
+    conn: &Rc<HttpConnResource>,
+    request: Request<Body>,
+    upgrade: Rc<HttpUpgradeState>,
+  ) -> Self {
     let size = request.body().size_hint();
+    let received_at = request
+      .extensions()
+      .get::<RequestReceivedAt>()
+      .map_or_else(SystemTime::now, |received_at| received_at.0);
+    let uri = request.uri().clone();
+    let target = req_target(&request);
+    let keep_alive = req_keep_alive(&request);
+    let on_upgrade =
+      request.extensions().get::<hyper_v014::upgrade::OnUpgrade>();
+    upgrade.pending.set(on_upgrade.is_some());
     Self {
-      _conn: conn.clone(),
+      conn: conn.clone(),
       rd: HttpRequestReader::Headers(request).into(),
       size,
       cancel_handle: CancelHandle::new(),
+      bytes_read: Cell::new(0),
+      received_at,
+      uri,
+      target,
+      keep_alive,
+      upgrade,
+    }
+  }
+
+  /// Switches from the request head to its body. Hyper's pending upgrade is
+  /// dropped along with the head.
+  fn start_body(&self, rd: &mut HttpRequestReader) {
+    if let HttpRequestReader::Headers(_) = rd {
+      let HttpRequestReader::Headers(request) = take(rd) else {
+        unreachable!();
+      };
+      let (parts, body) = request.into_parts();
+      *rd = HttpRequestReader::Body(parts.headers, body.peekable());
+      self.upgrade.pending.set(false);
+    }
+  }
+}
+
+impl HttpStreamReadResource {
+  /// Takes the whole body if it has already been received and is at most
+  /// `limit` bytes long. This never waits for more of the body to arrive.
+  fn take_inline_body(self: &Rc<Self>, limit: usize) -> Option<Bytes> {
+    let mut rd = RcRef::map(self, |r| &r.rd).try_borrow_mut()?;
+    self.start_body(&mut rd);
+    let HttpRequestReader::Body(_, body) = &mut *rd else {
+      return None;
+    };
+
+    let mut body = Pin::new(body);
+    let len = match body.as_mut().peek().now_or_never()? {
+      Some(Ok(chunk)) => chunk.len(),
+      Some(Err(_)) => return None, // Left for the next read to report.
+      None => return Some(Bytes::new()),
+    };
+    let max_body_size = self.conn.options.max_body_size.unwrap_or(u64::MAX);
+    // Hyper only knows that a body has ended after its last chunk if the
+    // body's length was known in advance.
+    let ended = body.as_ref().get_ref().get_ref().is_end_stream();
+    if len > limit || len as u64 > max_body_size || !ended {
+      return None;
+    }
+    // This unwrap is safe because `peek()` returned a chunk.
+    let Some(Ok(chunk)) = body.next().now_or_never().unwrap() else {
+      unreachable!();
+    };
+    self.bytes_read.set(len as u64);
+    HttpConnStats::add(&self.conn.stats.bytes_read, len);
+    Some(chunk)
+  }
+
+  fn body_consumed(self: &Rc<Self>) -> Result<bool, AnyError> {
+    let rd = RcRef::map(self, |r| &r.rd)
+      .try_borrow()
+      .ok_or_else(|| http_error("already in use"))?;
+    match &*rd {
+      HttpRequestReader::Headers(request) => Ok(request.body().is_end_stream()),
+      HttpRequestReader::Body(_, body) => Ok(body.is_terminated()),
+      HttpRequestReader::Closed => Ok(true),
+    }
+  }
+
+  async fn trailers(self: &Rc<Self>) -> Result<Option<HeaderMap>, AnyError> {
+    let mut rd = RcRef::map(self, |r| &r.rd).borrow_mut().await;
+
+    self.start_body(&mut rd);
+
+    let HttpRequestReader::Body(_, body) = &mut *rd else {
+      return Err(http_error("request body already consumed"));
+    };
+    if !matches!(Pin::new(&mut *body).peek().now_or_never(), Some(None)) {
+      return Err(http_error("request body not fully read"));
     }
+    body
+      .get_mut()
+      .trailers()
+      .await
+      .map_err(|err| hyper_error(&err))
   }
 }
 
@@ -386,23 +1418,22 @@ impl Resource for HttpStreamReadResource {
     "httpReadStream".into()
   }
 
+  /// Reads the next chunk of the request body. An empty view is only ever
+  /// returned once the body has ended: empty chunks from hyper are skipped,
+  /// and a zero `limit` is rejected rather than producing an empty read.
   fn read(self: Rc<Self>, limit: usize) -> AsyncResult<BufView> {
     Box::pin(async move {
+      if limit == 0 {
+        return Err(http_error("read limit must be greater than zero"));
+      }
+
       let mut rd = RcRef::map(&self, |r| &r.rd).borrow_mut().await;
 
-      let body = loop {
-        match &mut *rd {
-          HttpRequestReader::Headers(_) => {}
-          HttpRequestReader::Body(_, body) => break body,
-          HttpRequestReader::Closed => return Ok(BufView::empty()),
-        }
-        match take(&mut *rd) {
-          HttpRequestReader::Headers(request) => {
-            let (parts, body) = request.into_parts();
-            *rd = HttpRequestReader::Body(parts.headers, body.peekable());
-          }
-          _ => unreachable!(),
-        };
+      self.start_body(&mut rd);
+      let body = match &mut *rd {
+        HttpRequestReader::Body(_, body) => body,
+        HttpRequestReader::Closed => return Ok(BufView::empty()),
+        HttpRequestReader::Headers(_) => unreachable!(),
       };
 
       let fut = async {
@@ -411,6 +1442,14 @@ impl Resource for HttpStreamReadResource {
           match body.as_mut().peek_mut().await {
             Some(Ok(chunk)) if !chunk.is_empty() => {
               let len = min(limit, chunk.len());
+              let bytes_read = self.bytes_read.get() + len as u64;
+              if let Some(max_body_size) = self.conn.options.max_body_size {
+                if bytes_read > max_body_size {
+                  break Err(body_too_large_error());
+                }
+              }
+              self.bytes_read.set(bytes_read);
+              HttpConnStats::add(&self.conn.stats.bytes_read, len);
               let buf = chunk.split_to(len);
               let view = BufView::from(buf);
               break Ok(view);
@@ -423,7 +1462,7 @@ impl Resource for HttpStreamReadResource {
             // safely call `await` on it without creating a race condition.
             Some(_) => match body.as_mut().next().await.unwrap() {
               Ok(chunk) => assert!(chunk.is_empty()),
-              Err(err) => break Err(AnyError::from(err)),
+              Err(err) => break Err(hyper_error(&err)),
             },
             None => break Ok(BufView::empty()),
           }
@@ -431,7 +1470,18 @@ impl Resource for HttpStreamReadResource {
       };
 
       let cancel_handle = RcRef::map(&self, |r| &r.cancel_handle);
-      fut.try_or_cancel(cancel_handle).await
+      let fut = fut.try_or_cancel(cancel_handle);
+      match self.conn.options.body_read_timeout {
+        Some(timeout) => match tokio::time::timeout(timeout, fut).await {
+          Ok(result) => result,
+          Err(_) => {
+            // A client trickling the body must not keep it readable.
+            self.cancel_handle.cancel();
+            Err(custom_error("TimedOut", "request body read timed out"))
+          }
+        },
+        None => fut.await,
+      }
     })
   }
 
@@ -449,19 +1499,42 @@ impl HttpStreamWriteResource {
     conn: &Rc<HttpConnResource>,
     response_tx: oneshot::Sender<Response<Body>>,
     accept_encoding: Encoding,
+    is_head: bool,
+    is_http2: bool,
+    upgrade: Rc<HttpUpgradeState>,
   ) -> Self {
     Self {
       conn: conn.clone(),
       wr: HttpResponseWriter::Headers(response_tx).into(),
       accept_encoding,
+      is_head,
+      is_http2,
+      http10: Cell::new(false),
+      upgrade,
+      _request_permit: None,
     }
   }
 }
 
+/// Lets generic write ops stream into the response body once the headers
+/// are sent, with the same backpressure as `op_http_write`. Shutting the
+/// resource down ends the body like `op_http_shutdown`.
 impl Resource for HttpStreamWriteResource {
   fn name(&self) -> Cow<str> {
     "httpWriteStream".into()
   }
+
+  fn write(self: Rc<Self>, buf: BufView) -> AsyncResult<WriteOutcome> {
+    Box::pin(async move {
+      let nwritten = buf.len();
+      http_write(&self, Bytes::from(buf)).await?;
+      Ok(WriteOutcome::Full { nwritten })
+    })
+  }
+
+  fn shutdown(self: Rc<Self>) -> AsyncResult<()> {
+    Box::pin(async move { shutdown_response(&self).await })
+  }
 }
 
 /// The read half of an HTTP stream.
@@ -494,31 +1567,90 @@ impl Default for HttpResponseWriter {
   }
 }
 
-struct BodyUncompressedSender(Option<hyper_v014::body::Sender>);
+struct BodyUncompressedSender {
+  sender: Option<hyper_v014::body::Sender>,
+  // Bytes still to be written when the response declared a Content-Length.
+  remaining: Option<u64>,
+  // Chunks held back until `buffer_size` bytes are buffered.
+  buffer: BytesMut,
+  buffer_size: usize,
+}
 
 impl BodyUncompressedSender {
   fn sender(&mut self) -> &mut hyper_v014::body::Sender {
     // This is safe because we only ever take the sender out of the option
     // inside of the shutdown method.
-    self.0.as_mut().unwrap()
+    self.sender.as_mut().unwrap()
+  }
+
+  /// Accounts for a chunk that is about to be written, rejecting it if it
+  /// would make the body longer than its declared Content-Length.
+  fn reserve(&mut self, len: usize) -> Result<(), AnyError> {
+    if let Some(remaining) = &mut self.remaining {
+      *remaining = remaining.checked_sub(len as u64).ok_or_else(|| {
+        http_error("response body exceeds its Content-Length")
+      })?;
+    }
+    Ok(())
+  }
+
+  /// Adds a chunk to the write buffer, returning what should be sent now, if
+  /// anything.
+  fn buffered(&mut self, bytes: Bytes) -> Option<Bytes> {
+    if self.buffer.is_empty() && bytes.len() >= self.buffer_size {
+      return Some(bytes);
+    }
+    self.buffer.extend_from_slice(&bytes);
+    if self.buffer.len() < self.buffer_size {
+      return None;
+    }
+    Some(self.buffer.split().freeze())
+  }
+
+  /// Sends the chunks held back in the write buffer.
+  async fn flush_buffer(
+    &mut self,
+    stats: &HttpConnStats,
+  ) -> Result<(), hyper_v014::Error> {
+    if self.buffer.is_empty() {
+      return Ok(());
+    }
+    let bytes = self.buffer.split().freeze();
+    let len = bytes.len();
+    self.sender().send_data(bytes).await?;
+    HttpConnStats::add(&stats.bytes_written, len);
+    Ok(())
   }
 
-  fn shutdown(mut self) {
+  fn shutdown(mut self) -> Result<(), AnyError> {
+    if matches!(self.remaining, Some(remaining) if remaining > 0) {
+      // Dropping self aborts the body, so the client does not wait for the
+      // missing bytes.
+      return Err(http_error(
+        "response body is shorter than its Content-Length",
+      ));
+    }
     // take the sender out of self so that when self is dropped at the end of
     // this block, it doesn't get aborted
-    self.0.take();
+    self.sender.take();
+    Ok(())
   }
 }
 
 impl From<hyper_v014::body::Sender> for BodyUncompressedSender {
   fn from(sender: hyper_v014::body::Sender) -> Self {
-    BodyUncompressedSender(Some(sender))
+    BodyUncompressedSender {
+      sender: Some(sender),
+      remaining: None,
+      buffer: BytesMut::new(),
+      buffer_size: 0,
+    }
   }
 }
 
 impl Drop for BodyUncompressedSender {
   fn drop(&mut self) {
-    if let Some(sender) = self.0.take() {
+    if let Some(sender) = self.sender.take() {
       sender.abort();
     }
   }
@@ -538,35 +1670,335 @@ struct NextRequestResponse(
   String,
   // url:
   String,
+  // version:
+  &'static str,
+  // inline_body:
+  Option<ToJsBuffer>,
+  // body_size:
+  // The exact length of the request body, or `None` if it is not known in
+  // advance (eg: a chunked body).
+  Option<u64>,
 );
 
+/// Returns the protocol the client negotiated via ALPN, or `null` if the
+/// connection is plaintext or no protocol was negotiated.
 #[op2(async)]
-#[serde]
-async fn op_http_accept(
+#[string]
+async fn op_http_get_alpn(
   state: Rc<RefCell<OpState>>,
   #[smi] rid: ResourceId,
-) -> Result<Option<NextRequestResponse>, AnyError> {
+) -> Result<Option<String>, AnyError> {
   let conn = state.borrow().resource_table.get::<HttpConnResource>(rid)?;
+  let alpn = conn.tls_info().await.and_then(|info| info.handshake.alpn);
+  Ok(alpn.map(|alpn| String::from_utf8_lossy(&alpn).into_owned()))
+}
 
-  match conn.accept().await {
-    Ok(Some((read_stream, write_stream, method, url))) => {
-      let read_stream_rid = state
-        .borrow_mut()
-        .resource_table
-        .add_rc(Rc::new(read_stream));
-      let write_stream_rid = state
-        .borrow_mut()
-        .resource_table
-        .add_rc(Rc::new(write_stream));
-      let r =
-        NextRequestResponse(read_stream_rid, write_stream_rid, method, url);
-      Ok(Some(r))
-    }
+/// Returns the hostname the client asked for via SNI during the TLS
+/// handshake, which may differ from the `Host` header, or `null` if the
+/// connection is plaintext or the client sent none.
+#[op2(async)]
+#[string]
+async fn op_http_tls_sni(
+  state: Rc<RefCell<OpState>>,
+  #[smi] rid: ResourceId,
+) -> Result<Option<String>, AnyError> {
+  let conn = state.borrow().resource_table.get::<HttpConnResource>(rid)?;
+  Ok(conn.tls_info().await.and_then(|info| info.handshake.sni))
+}
+
+/// Returns the number of connections that are being served. A connection
+/// stops counting once it has closed, even if its resource is still open.
+#[op2(fast)]
+fn op_http_conn_count(state: &mut OpState) -> u32 {
+  state.borrow::<HttpConnCount>().0.get() as u32
+}
+
+/// Returns the number of requests accepted on the connection so far and the
+/// request and response body bytes transferred by them.
+#[op2]
+#[serde]
+fn op_http_conn_stats(
+  state: &mut OpState,
+  #[smi] rid: ResourceId,
+) -> Result<HttpConnStats, AnyError> {
+  let conn = state.resource_table.get::<HttpConnResource>(rid)?;
+  Ok(conn.stats.clone())
+}
+
+/// Returns the hostname and port of the client, or `null` if the peer has no
+/// IP address (eg: on Unix domain sockets).
+#[op2]
+#[serde]
+fn op_http_get_peer_addr(
+  state: &mut OpState,
+  #[smi] rid: ResourceId,
+) -> Result<Option<(String, u16)>, AnyError> {
+  let conn = state.resource_table.get::<HttpConnResource>(rid)?;
+  Ok(conn.peer_ip_addr())
+}
+
+/// An entry of `op_http_active_conns`.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct HttpActiveConn {
+  rid: ResourceId,
+  peer_addr: Option<(String, u16)>,
+  requests: u64,
+}
+
+/// Lists the `Deno.serveHttp` connection resources that are still open, with
+/// their client address and the number of requests accepted on them, eg: to
+/// find stuck connections and close them with `Deno.core.close()`.
+#[op2]
+#[serde]
+fn op_http_active_conns(state: &mut OpState) -> Vec<HttpActiveConn> {
+  active_conns(&state.resource_table)
+}
+
+fn active_conns(resource_table: &ResourceTable) -> Vec<HttpActiveConn> {
+  let rids = resource_table
+    .names()
+    .filter(|(_, name)| name == "httpConn")
+    .map(|(rid, _)| rid)
+    .collect::<Vec<_>>();
+  rids
+    .into_iter()
+    .filter_map(|rid| {
+      let conn = resource_table.get::<HttpConnResource>(rid).ok()?;
+      Some(HttpActiveConn {
+        rid,
+        peer_addr: conn.peer_ip_addr(),
+        requests: conn.stats.requests.get(),
+      })
+    })
+    .collect()
+}
+
+/// Enables TCP keepalive on the connection, or disables it when `keepalive`
+/// is `null`, so that peers that silently went away (eg: behind a NAT) are
+/// detected. Returns `false` without doing anything if the connection is not
+/// served over a plain TCP socket: the socket under a TLS stream is not
+/// reachable, and Unix sockets have no keepalive. After an upgrade the socket
+/// belongs to the upgraded stream, so set it before upgrading; the setting
+/// carries over.
+#[op2]
+fn op_http_set_tcp_keepalive(
+  state: &mut OpState,
+  #[smi] rid: ResourceId,
+  #[serde] keepalive: Option<HttpTcpKeepalive>,
+) -> Result<bool, AnyError> {
+  let conn = state.resource_table.get::<HttpConnResource>(rid)?;
+  let result = conn.with_tcp_socket(|socket| match &keepalive {
+    Some(keepalive) => socket.set_tcp_keepalive(&tcp_keepalive(keepalive)),
+    None => socket.set_keepalive(false),
+  });
+  match result {
+    Some(result) => result.map(|_| true).map_err(AnyError::from),
+    None => Ok(false),
+  }
+}
+
+/// Reports whether TCP keepalive is enabled on the connection, or `null` if
+/// it is not served over a plain TCP socket.
+#[op2]
+fn op_http_tcp_keepalive(
+  state: &mut OpState,
+  #[smi] rid: ResourceId,
+) -> Result<Option<bool>, AnyError> {
+  let conn = state.resource_table.get::<HttpConnResource>(rid)?;
+  let enabled = conn.with_tcp_socket(|socket| socket.keepalive());
+  Ok(enabled.transpose()?)
+}
+
+fn tcp_keepalive(keepalive: &HttpTcpKeepalive) -> TcpKeepalive {
+  let mut params = TcpKeepalive::new();
+  if let Some(idle) = keepalive.idle {
+    params = params.with_time(idle);
+  }
+  #[cfg(any(
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "ios",
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "windows",
+  ))]
+  if let Some(interval) = keepalive.interval {
+    params = params.with_interval(interval);
+  }
+  #[cfg(any(
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "ios",
+    target_os = "linux",
+    target_os = "macos",
+  ))]
+  if let Some(count) = keepalive.count {
+    params = params.with_retries(count);
+  }
+  params
+}
+
+/// Returns the DER certificate chain the client presented during the TLS
+/// handshake, leaf first, or `null` if the connection is plaintext or the
+/// client sent no certificate.
+#[op2(async)]
+#[serde]
+async fn op_http_get_peer_certificates(
+  state: Rc<RefCell<OpState>>,
+  #[smi] rid: ResourceId,
+) -> Result<Option<Vec<ToJsBuffer>>, AnyError> {
+  let conn = state.borrow().resource_table.get::<HttpConnResource>(rid)?;
+  let Some(info) = conn.tls_info().await else {
+    return Ok(None);
+  };
+  if info.peer_certificates.is_empty() {
+    return Ok(None);
+  }
+  let certs = info.peer_certificates.iter();
+  Ok(Some(certs.map(|cert| cert.clone().into()).collect()))
+}
+
+/// Stops dispatching new requests on the connection while letting in-flight
+/// responses finish. Resolves once the connection has closed.
+#[op2(async)]
+async fn op_http_graceful_shutdown(
+  state: Rc<RefCell<OpState>>,
+  #[smi] rid: ResourceId,
+) -> Result<(), AnyError> {
+  let conn = state.borrow().resource_table.get::<HttpConnResource>(rid)?;
+  conn.graceful_shutdown_handle.cancel();
+  conn.closed().await
+}
+
+/// Why the requests on a connection are being cancelled.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum HttpCancelReason {
+  /// The server is shutting the connection down gracefully, by closing the
+  /// resource or with `op_http_graceful_shutdown`. In-flight responses can
+  /// still be completed.
+  Shutdown,
+  /// The server dropped the connection, eg: after a write timeout or to
+  /// reset an HTTP/1 response. Nothing more can be sent.
+  Abort,
+  /// The client went away or the connection failed.
+  Disconnect,
+}
+
+impl HttpConnResource {
+  /// Resolves once requests on the connection are cancelled, with the reason.
+  async fn cancelled(&self) -> HttpCancelReason {
+    let cancelled = pending::<()>()
+      .or_cancel(&self.cancel_handle)
+      .or_cancel(&self.graceful_shutdown_handle)
+      .or_cancel(&self.abort_handle);
+    let _ = select(pin!(cancelled), self.closed_fut.clone()).await;
+    if self.abort_handle.is_canceled() {
+      HttpCancelReason::Abort
+    } else if self.cancel_handle.is_canceled()
+      || self.graceful_shutdown_handle.is_canceled()
+    {
+      HttpCancelReason::Shutdown
+    } else {
+      HttpCancelReason::Disconnect
+    }
+  }
+}
+
+/// Resolves once the connection starts shutting down or is closed, with the
+/// reason: `"shutdown"`, `"abort"` or `"disconnect"` (see `HttpCancelReason`).
+/// It never rejects, so it can back an `AbortSignal` for handlers that want to
+/// stop work early, and lets them finish up only on a graceful shutdown.
+#[op2(async)]
+#[serde]
+async fn op_http_request_on_cancel(
+  state: Rc<RefCell<OpState>>,
+  #[smi] rid: ResourceId,
+) -> Result<HttpCancelReason, AnyError> {
+  let conn = state.borrow().resource_table.get::<HttpConnResource>(rid)?;
+  Ok(conn.cancelled().await)
+}
+
+#[op2(async)]
+#[serde]
+async fn op_http_accept(
+  state: Rc<RefCell<OpState>>,
+  #[smi] rid: ResourceId,
+) -> Result<Option<NextRequestResponse>, AnyError> {
+  let conn = state.borrow().resource_table.get::<HttpConnResource>(rid)?;
+  let limit = state.borrow().borrow::<HttpRequestLimit>().clone();
+
+  match accept_limited(&conn, &limit).await {
+    Ok(Some((read_stream, write_stream, method, url, version))) => {
+      let read_stream = Rc::new(read_stream);
+      let body_size = read_stream.size.exact();
+      // GET and HEAD request bodies are not exposed to JS.
+      let inline_body = match conn.options.inline_body_size {
+        Some(limit) if method != "GET" && method != "HEAD" => {
+          read_stream.take_inline_body(limit)
+        }
+        _ => None,
+      };
+      let read_stream_rid =
+        state.borrow_mut().resource_table.add_rc(read_stream);
+      let write_stream_rid = state
+        .borrow_mut()
+        .resource_table
+        .add_rc(Rc::new(write_stream));
+      conn.track_streams(
+        &state.borrow().resource_table,
+        [read_stream_rid, write_stream_rid],
+      );
+      let r = NextRequestResponse(
+        read_stream_rid,
+        write_stream_rid,
+        method,
+        url,
+        version,
+        inline_body.map(|body| Vec::from(body).into()),
+        body_size,
+      );
+      Ok(Some(r))
+    }
     Ok(None) => Ok(None),
-    Err(err) => Err(err),
+    Err(err) => {
+      // The streams of in-flight requests can't make progress anymore.
+      conn.close_streams(&state);
+      Err(err)
+    }
   }
 }
 
+/// Accepts the next request, then holds it back until `limit` allows it. The
+/// limit is only waited for once a request has arrived, so that connections
+/// idling between requests do not count against it.
+async fn accept_limited(
+  conn: &Rc<HttpConnResource>,
+  limit: &HttpRequestLimit,
+) -> Result<
+  Option<(
+    HttpStreamReadResource,
+    HttpStreamWriteResource,
+    String,
+    String,
+    &'static str,
+  )>,
+  AnyError,
+> {
+  let Some((read_stream, mut write_stream, method, url, version)) =
+    conn.accept().await?
+  else {
+    return Ok(None);
+  };
+  match limit.acquire().or_cancel(&conn.cancel_handle).await {
+    Ok(permit) => write_stream._request_permit = permit,
+    // The connection is closing, which ends it like a closed connection.
+    Err(_) => return Ok(None),
+  }
+  Ok(Some((read_stream, write_stream, method, url, version)))
+}
+
 fn req_url(
   req: &hyper_v014::Request<hyper_v014::Body>,
   scheme: &'static str,
@@ -574,15 +2006,16 @@ fn req_url(
 ) -> String {
   let host: Cow<str> = match addr {
     HttpSocketAddr::IpSocket(addr) => {
+      // Prefer the authority of an absolute-form request target, then the
+      // `Host` header, and finally the address of the listening socket. The
+      // port is only left out when it is the default port for the scheme.
       if let Some(auth) = req.uri().authority() {
-        match addr.port() {
-          443 if scheme == "https" => Cow::Borrowed(auth.host()),
-          80 if scheme == "http" => Cow::Borrowed(auth.host()),
+        match auth.port_u16() {
+          Some(443) if scheme == "https" => Cow::Borrowed(auth.host()),
+          Some(80) if scheme == "http" => Cow::Borrowed(auth.host()),
           _ => Cow::Borrowed(auth.as_str()), // Includes port number.
         }
-      } else if let Some(host) = req.uri().host() {
-        Cow::Borrowed(host)
-      } else if let Some(host) = req.headers().get("HOST") {
+      } else if let Some(host) = req.headers().get(hyper_v014::header::HOST) {
         match host.to_str() {
           Ok(host) => Cow::Borrowed(host),
           Err(_) => Cow::Owned(
@@ -601,18 +2034,21 @@ fn req_url(
     // There is no standard way for unix domain socket URLs
     // nginx and nodejs request use http://unix:[socket_path]:/ but it is not a valid URL
     // httpie uses http+unix://[percent_encoding_of_path]/ which we follow
+    // Unnamed sockets (eg: from `socketpair`) have no path to encode, so they
+    // fall back to `localhost`.
     #[cfg(unix)]
-    HttpSocketAddr::UnixSocket(addr) => Cow::Owned(
-      percent_encoding::percent_encode(
-        addr
-          .as_pathname()
-          .and_then(|x| x.to_str())
-          .unwrap_or_default()
-          .as_bytes(),
-        percent_encoding::NON_ALPHANUMERIC,
-      )
-      .to_string(),
-    ),
+    HttpSocketAddr::UnixSocket(addr) => {
+      match addr.as_pathname().and_then(|x| x.to_str()) {
+        Some(path) => Cow::Owned(
+          percent_encoding::percent_encode(
+            path.as_bytes(),
+            percent_encoding::NON_ALPHANUMERIC,
+          )
+          .to_string(),
+        ),
+        None => Cow::Borrowed("localhost"),
+      }
+    }
   };
   let path = req
     .uri()
@@ -622,19 +2058,57 @@ fn req_url(
   [scheme, "://", &host, path].concat()
 }
 
+/// Returns the scheme from the `proto` parameter of the first `Forwarded`
+/// element, which was added by the proxy closest to the client, or else from
+/// the first `X-Forwarded-Proto` value.
+fn forwarded_scheme(headers: &HeaderMap) -> Option<&'static str> {
+  let forwarded = headers
+    .get(hyper_v014::header::FORWARDED)
+    .and_then(|value| value.to_str().ok())
+    .and_then(|value| {
+      value.split(',').next()?.split(';').find_map(|pair| {
+        let (name, value) = pair.trim().split_once('=')?;
+        name
+          .eq_ignore_ascii_case("proto")
+          .then(|| value.trim_matches('"'))
+      })
+    });
+  let proto = forwarded.or_else(|| {
+    let value = headers.get("x-forwarded-proto")?.to_str().ok()?;
+    value.split(',').next()
+  })?;
+  match proto.trim() {
+    proto if proto.eq_ignore_ascii_case("https") => Some("https"),
+    proto if proto.eq_ignore_ascii_case("http") => Some("http"),
+    _ => None,
+  }
+}
+
+fn req_version(version: hyper_v014::Version) -> &'static str {
+  match version {
+    hyper_v014::Version::HTTP_09 => "HTTP/0.9",
+    hyper_v014::Version::HTTP_10 => "HTTP/1.0",
+    hyper_v014::Version::HTTP_11 => "HTTP/1.1",
+    hyper_v014::Version::HTTP_2 => "HTTP/2.0",
+    hyper_v014::Version::HTTP_3 => "HTTP/3.0",
+    _ => unreachable!(),
+  }
+}
+
 fn req_headers(
   header_map: &HeaderMap<HeaderValue>,
+  separate_cookies: bool,
 ) -> Vec<(ByteString, ByteString)> {
   // We treat cookies specially, because we don't want them to get them
   // mangled by the `Headers` object in JS. What we do is take all cookie
   // headers and concat them into a single cookie header, separated by
-  // semicolons.
+  // semicolons. Connections can opt out of this with `separate_cookies`.
   let cookie_sep = "; ".as_bytes();
   let mut cookies = vec![];
 
   let mut headers = Vec::with_capacity(header_map.len());
   for (name, value) in header_map.iter() {
-    if name == hyper_v014::header::COOKIE {
+    if name == hyper_v014::header::COOKIE && !separate_cookies {
       cookies.push(value.as_bytes());
     } else {
       let name: &[u8] = name.as_ref();
@@ -650,6 +2124,18 @@ fn req_headers(
   headers
 }
 
+/// Sends the response head, with `data` as the whole body if it is given.
+/// Otherwise the body is streamed with the write ops.
+///
+/// Hyper picks the HTTP/1.1 framing: a `Content-Length` for a buffered body
+/// and chunked encoding for a streamed one. Handlers can choose it instead
+/// with headers: `Transfer-Encoding: chunked` makes even a buffered body
+/// chunked, and a `Content-Length` sends a streamed body without chunked
+/// encoding, in which case the body has to be exactly that long. HTTP/1.0
+/// clients and HTTP/2 never get chunked encoding.
+///
+/// A `101` for a request that cannot be upgraded is sent as a `400` instead,
+/// and the op fails.
 #[op2(async)]
 async fn op_http_write_headers(
   state: Rc<RefCell<OpState>>,
@@ -662,7 +2148,15 @@ async fn op_http_write_headers(
     .borrow_mut()
     .resource_table
     .get::<HttpStreamWriteResource>(rid)?;
+  write_headers(&stream, status, headers, data).await
+}
 
+async fn write_headers(
+  stream: &Rc<HttpStreamWriteResource>,
+  status: u16,
+  headers: Vec<(ByteString, ByteString)>,
+  data: Option<StringOrBuffer>,
+) -> Result<(), AnyError> {
   // Track supported encoding
   let encoding = stream.accept_encoding;
 
@@ -672,17 +2166,45 @@ async fn op_http_write_headers(
 
   // Add headers
   hmap.reserve(headers.len() + 2);
-  for (k, v) in headers.into_iter() {
-    let v: Vec<u8> = v.into();
-    hmap.append(
-      HeaderName::try_from(k.as_slice())?,
-      HeaderValue::try_from(v)?,
-    );
-  }
+  append_headers(hmap, headers)?;
+  merge_default_headers(hmap, &stream.conn.options.default_headers);
   ensure_vary_accept_encoding(hmap);
+  ensure_event_stream_no_cache(hmap);
+  if status == StatusCode::SWITCHING_PROTOCOLS.as_u16() {
+    if !stream.upgrade.pending.get() {
+      // Hyper would send the 101 and then close the connection, so answer
+      // with an error the client can see instead.
+      let mut response = Response::new(Body::empty());
+      *response.status_mut() = StatusCode::BAD_REQUEST;
+      send_response(stream, response, HttpResponseWriter::Closed).await?;
+      return Err(http_error(
+        "cannot upgrade a request that did not ask for it or whose body was used",
+      ));
+    }
+    *stream.upgrade.websocket_protocol.borrow_mut() = websocket_protocol(hmap);
+  }
+
+  if stream.is_head {
+    // Keep the headers a GET response would have, including the length of a
+    // buffered body, but leave the body itself out.
+    if let Some(data) = &data {
+      if !hmap.contains_key(hyper_v014::header::CONTENT_LENGTH)
+        && !hmap.contains_key(hyper_v014::header::TRANSFER_ENCODING)
+      {
+        hmap.insert(
+          hyper_v014::header::CONTENT_LENGTH,
+          HeaderValue::from(data.len()),
+        );
+      }
+    }
+    let response = builder.status(status).body(Body::empty())?;
+    return send_response(stream, response, HttpResponseWriter::Closed).await;
+  }
 
-  let accepts_compression =
-    matches!(encoding, Encoding::Brotli | Encoding::Gzip);
+  let accepts_compression = matches!(
+    encoding,
+    Encoding::Brotli | Encoding::Gzip | Encoding::Deflate
+  );
   let compressing = accepts_compression
     && (matches!(data, Some(ref data) if data.len() > 20) || data.is_none())
     && should_compress(hmap);
@@ -697,21 +2219,75 @@ async fn op_http_write_headers(
       HeaderValue::from_static(match encoding {
         Encoding::Brotli => "br",
         Encoding::Gzip => "gzip",
+        Encoding::Deflate => "deflate",
         _ => unreachable!(), // Forbidden by accepts_compression
       }),
     );
   }
 
-  let (new_wr, body) = http_response(data, compressing, encoding)?;
-  let body = builder.status(status).body(body)?;
+  let len = data.as_ref().map_or(0, |data| data.len());
+  let content_length = content_length(hmap);
+  // Hyper sends a buffered body in full but keeps the declared length, so a
+  // mismatch would corrupt the response. A 304 response declares the length
+  // of the body it leaves out.
+  if let (Some(content_length), Some(_)) = (content_length, &data) {
+    if !compressing
+      && content_length != len as u64
+      && status != StatusCode::NOT_MODIFIED.as_u16()
+    {
+      return Err(http_error(
+        "response body does not match its Content-Length",
+      ));
+    }
+  }
+  let (mut new_wr, body) =
+    http_response(data, compressing, encoding, &stream.conn.options)?;
+  // A streamed body with a Content-Length is sent without chunked framing, so
+  // it has to match the declared length exactly.
+  if let HttpResponseWriter::BodyUncompressed(body) = &mut new_wr {
+    body.remaining = content_length;
+  }
+  let response = builder.status(status).body(body)?;
+  send_response(stream, response, new_wr).await?;
+  HttpConnStats::add(&stream.conn.stats.bytes_written, len);
+  Ok(())
+}
+
+fn content_length(hmap: &HeaderMap) -> Option<u64> {
+  hmap
+    .get(hyper_v014::header::CONTENT_LENGTH)
+    .and_then(|v| v.to_str().ok())
+    .and_then(|v| v.parse::<u64>().ok())
+}
 
-  let mut old_wr = RcRef::map(&stream, |r| &r.wr).borrow_mut().await;
-  let response_tx = match replace(&mut *old_wr, new_wr) {
-    HttpResponseWriter::Headers(response_tx) => response_tx,
-    _ => return Err(http_error("response headers already sent")),
+/// Hands the response head to hyper and switches the stream to `new_wr` for
+/// the body.
+async fn send_response(
+  stream: &Rc<HttpStreamWriteResource>,
+  mut response: Response<Body>,
+  new_wr: HttpResponseWriter,
+) -> Result<(), AnyError> {
+  let mut old_wr = RcRef::map(stream, |r| &r.wr).borrow_mut().await;
+  // Responding twice must leave the response that is already in progress
+  // untouched, so check the state before replacing the writer.
+  if !matches!(*old_wr, HttpResponseWriter::Headers(_)) {
+    return Err(http_error("response headers already sent"));
+  }
+  let HttpResponseWriter::Headers(response_tx) = replace(&mut *old_wr, new_wr)
+  else {
+    unreachable!();
   };
 
-  match response_tx.send(body) {
+  if stream.http10.get() {
+    *response.version_mut() = hyper_v014::Version::HTTP_10;
+    // Hyper only closes the connection after an HTTP/1.0 response by itself
+    // when the request was HTTP/1.0 too.
+    response.headers_mut().insert(
+      hyper_v014::header::CONNECTION,
+      HeaderValue::from_static("close"),
+    );
+  }
+  match response_tx.send(response) {
     Ok(_) => Ok(()),
     Err(_) => {
       stream.conn.closed().await?;
@@ -720,6 +2296,295 @@ async fn op_http_write_headers(
   }
 }
 
+/// Sends an interim `100 Continue` response to a client that sent
+/// `Expect: 100-continue`. Hyper sends it automatically the first time the
+/// request body is read, so this op is only needed to acknowledge the request
+/// before reading the body. Handlers that want to reject the body (eg: with a
+/// `417`) should respond without calling this op or reading the body.
+#[op2(async)]
+async fn op_http_write_continue(
+  state: Rc<RefCell<OpState>>,
+  #[smi] rid: ResourceId,
+) -> Result<(), AnyError> {
+  let stream = state
+    .borrow()
+    .resource_table
+    .get::<HttpStreamReadResource>(rid)?;
+  let mut rd = RcRef::map(&stream, |r| &r.rd).borrow_mut().await;
+
+  stream.start_body(&mut rd);
+
+  match &mut *rd {
+    HttpRequestReader::Body(_, body) => {
+      // Polling the body tells hyper that it is wanted, which makes the
+      // connection write the interim response. A chunk that is already
+      // available remains buffered in the peekable body.
+      let _ = Pin::new(body).peek().now_or_never();
+      Ok(())
+    }
+    _ => Err(http_error("request body already consumed")),
+  }
+}
+
+/// Reads the whole request body into a single buffer. Fails with "request
+/// body too large" if the body is longer than `max_size` bytes.
+#[op2(async)]
+#[serde]
+async fn op_http_read_all(
+  state: Rc<RefCell<OpState>>,
+  #[smi] rid: ResourceId,
+  #[number] max_size: u64,
+) -> Result<ToJsBuffer, AnyError> {
+  let stream = state
+    .borrow()
+    .resource_table
+    .get::<HttpStreamReadResource>(rid)?;
+
+  // The declared length is only a hint, the client may never send the body.
+  let capacity = min(stream.size.lower(), max_size).min(64 * 1024); // 64KB
+  let mut buf = Vec::with_capacity(capacity as usize);
+  loop {
+    let view = stream.clone().read(64 * 1024).await?; // 64KB
+    if view.is_empty() {
+      break;
+    }
+    if (buf.len() + view.len()) as u64 > max_size {
+      return Err(body_too_large_error());
+    }
+    buf.extend_from_slice(&view);
+  }
+  Ok(buf.into())
+}
+
+/// Reads the next chunk of the request body as hyper received it, however
+/// large, or `null` at the end of the body. Unlike reads of the resource it is
+/// never split to fit a buffer, so HTTP/2 `DATA` frames keep their
+/// boundaries. HTTP/1 chunked bodies never merge two chunks into one, but a
+/// chunk that arrives over several socket reads comes in several pieces.
+#[op2(async)]
+#[serde]
+async fn op_http_read_frame(
+  state: Rc<RefCell<OpState>>,
+  #[smi] rid: ResourceId,
+) -> Result<Option<ToJsBuffer>, AnyError> {
+  let stream = state
+    .borrow()
+    .resource_table
+    .get::<HttpStreamReadResource>(rid)?;
+  let chunk = read_chunk(stream, usize::MAX).await?;
+  Ok(chunk.map(|view| view.to_vec().into()))
+}
+
+/// Reads at most `limit` bytes of the request body into a new buffer sized to
+/// fit them, or `null` at the end of the body. The rest of a larger chunk is
+/// returned by the next read.
+#[op2(async)]
+#[serde]
+async fn op_http_read_chunk(
+  state: Rc<RefCell<OpState>>,
+  #[smi] rid: ResourceId,
+  #[number] limit: usize,
+) -> Result<Option<ToJsBuffer>, AnyError> {
+  let stream = state
+    .borrow()
+    .resource_table
+    .get::<HttpStreamReadResource>(rid)?;
+  let chunk = read_chunk(stream, limit).await?;
+  Ok(chunk.map(|view| view.to_vec().into()))
+}
+
+async fn read_chunk(
+  stream: Rc<HttpStreamReadResource>,
+  limit: usize,
+) -> Result<Option<BufView>, AnyError> {
+  let view = stream.read(limit).await?;
+  Ok((!view.is_empty()).then_some(view))
+}
+
+/// Copies the request body into the writable resource `file_rid`, usually a
+/// file, without passing it through JS. Resolves to the number of bytes
+/// written. Fails with "request body too large" once the body grows past
+/// `max_size` bytes, in which case the bytes before that have already been
+/// written.
+#[op2(async)]
+#[number]
+async fn op_http_read_to_file(
+  state: Rc<RefCell<OpState>>,
+  #[smi] rid: ResourceId,
+  #[smi] file_rid: ResourceId,
+  #[number] max_size: u64,
+) -> Result<u64, AnyError> {
+  let (stream, file) = {
+    let resource_table = &state.borrow().resource_table;
+    (
+      resource_table.get::<HttpStreamReadResource>(rid)?,
+      resource_table.get_any(file_rid)?,
+    )
+  };
+  read_to_resource(stream, file, max_size).await
+}
+
+async fn read_to_resource(
+  stream: Rc<HttpStreamReadResource>,
+  resource: Rc<dyn Resource>,
+  max_size: u64,
+) -> Result<u64, AnyError> {
+  let mut written = 0;
+  loop {
+    let view = stream.clone().read(64 * 1024).await?; // 64KB
+    if view.is_empty() {
+      break;
+    }
+    written += view.len() as u64;
+    if written > max_size {
+      return Err(body_too_large_error());
+    }
+    resource.clone().write_all(view).await?;
+  }
+  Ok(written)
+}
+
+/// Returns when hyper finished receiving the request head, in milliseconds
+/// since the Unix epoch.
+#[op2(fast)]
+fn op_http_request_received_at(
+  state: &mut OpState,
+  #[smi] rid: ResourceId,
+) -> Result<f64, AnyError> {
+  let stream = state.resource_table.get::<HttpStreamReadResource>(rid)?;
+  let since_epoch = stream
+    .received_at
+    .duration_since(SystemTime::UNIX_EPOCH)
+    .unwrap_or_default();
+  Ok(since_epoch.as_secs_f64() * 1000.0)
+}
+
+/// Returns the request path and its decoded query pairs, so routers can skip
+/// parsing the full URL in JavaScript. The path is not percent-decoded.
+#[op2]
+#[serde]
+fn op_http_request_query(
+  state: &mut OpState,
+  #[smi] rid: ResourceId,
+) -> Result<(String, Vec<(String, String)>), AnyError> {
+  let stream = state.resource_table.get::<HttpStreamReadResource>(rid)?;
+  Ok(req_path_and_query(&stream.uri))
+}
+
+/// Replaces the body of a request with a supported `Content-Encoding` by its
+/// decoded stream.
+fn decompress_request_body(request: &mut Request<Body>) {
+  let headers = request.headers_mut();
+  let Some(encoding) = headers
+    .get(hyper_v014::header::CONTENT_ENCODING)
+    .and_then(|encoding| encoding.to_str().ok())
+  else {
+    return;
+  };
+  let encoding = encoding.trim().to_ascii_lowercase();
+  if !matches!(&*encoding, "gzip" | "x-gzip" | "deflate" | "br") {
+    return;
+  }
+  headers.remove(hyper_v014::header::CONTENT_ENCODING);
+  headers.remove(hyper_v014::header::CONTENT_LENGTH);
+
+  let body = TryStreamExt::map_err(take(request.body_mut()), |err| {
+    io::Error::new(io::ErrorKind::Other, err)
+  });
+  let reader = StreamReader::new(body);
+  *request.body_mut() = match &*encoding {
+    "br" => Body::wrap_stream(ReaderStream::new(BrotliDecoder::new(reader))),
+    // `deflate` is the zlib format, not raw deflate.
+    "deflate" => Body::wrap_stream(ReaderStream::new(ZlibDecoder::new(reader))),
+    _ => Body::wrap_stream(ReaderStream::new(GzipDecoder::new(reader))),
+  };
+}
+
+/// Returns the request target as the client sent it, unlike the `url` from
+/// `op_http_accept`, which is always absolute. This is the origin-form
+/// (`/a?b`), absolute-form (`http://example.com/a?b`), authority-form
+/// (`example.com:443`, for `CONNECT`) or asterisk-form (`*`, for `OPTIONS`).
+#[op2]
+#[string]
+fn op_http_request_target(
+  state: &mut OpState,
+  #[smi] rid: ResourceId,
+) -> Result<String, AnyError> {
+  let stream = state.resource_table.get::<HttpStreamReadResource>(rid)?;
+  Ok(stream.target.clone())
+}
+
+fn req_target(req: &hyper_v014::Request<hyper_v014::Body>) -> String {
+  let uri = req.uri();
+  // HTTP/2 has no request line: hyper builds the URI from the `:scheme`,
+  // `:authority` and `:path` pseudo-headers, and `:path` is the target.
+  // `CONNECT` requests only have an `:authority`.
+  if req.version() == hyper_v014::Version::HTTP_2 {
+    return match uri.path_and_query() {
+      Some(path) if req.method() != Method::CONNECT => path.to_string(),
+      _ => uri
+        .authority()
+        .map(|auth| auth.to_string())
+        .unwrap_or_default(),
+    };
+  }
+  uri.to_string()
+}
+
+/// Returns whether the connection will be kept open for another request
+/// after this one's response. HTTP/1.1 connections are kept alive unless the
+/// request has `Connection: close`, and HTTP/1.0 ones only when it has
+/// `Connection: keep-alive`. This turns false once the connection starts
+/// shutting down.
+///
+/// A response with `Connection: close` makes hyper close the connection
+/// after sending it, whatever the request asked for.
+#[op2(fast)]
+fn op_http_request_keep_alive(
+  state: &mut OpState,
+  #[smi] rid: ResourceId,
+) -> Result<bool, AnyError> {
+  let stream = state.resource_table.get::<HttpStreamReadResource>(rid)?;
+  let conn = &stream.conn;
+  Ok(
+    stream.keep_alive
+      && !conn.cancel_handle.is_canceled()
+      && !conn.graceful_shutdown_handle.is_canceled()
+      && !conn.abort_handle.is_canceled(),
+  )
+}
+
+/// Mirrors how hyper decides to keep an HTTP/1 connection alive. HTTP/2
+/// connections always outlive their streams.
+fn req_keep_alive(req: &hyper_v014::Request<hyper_v014::Body>) -> bool {
+  let has = |token: &str| {
+    req
+      .headers()
+      .get_all(hyper_v014::header::CONNECTION)
+      .iter()
+      .filter_map(|value| value.to_str().ok())
+      .flat_map(|value| value.split(','))
+      .any(|value| value.trim().eq_ignore_ascii_case(token))
+  };
+  match req.version() {
+    hyper_v014::Version::HTTP_11 => !has("close"),
+    hyper_v014::Version::HTTP_10 | hyper_v014::Version::HTTP_09 => {
+      has("keep-alive")
+    }
+    _ => true,
+  }
+}
+
+fn req_path_and_query(
+  uri: &hyper_v014::Uri,
+) -> (String, Vec<(String, String)>) {
+  let query = uri.query().unwrap_or_default();
+  let pairs = form_urlencoded::parse(query.as_bytes())
+    .into_owned()
+    .collect();
+  (uri.path().to_string(), pairs)
+}
+
 #[op2]
 #[serde]
 fn op_http_headers(
@@ -730,22 +2595,280 @@ fn op_http_headers(
   let rd = RcRef::map(&stream, |r| &r.rd)
     .try_borrow()
     .ok_or_else(|| http_error("already in use"))?;
+  let separate_cookies = stream.conn.options.separate_cookies;
   match &*rd {
-    HttpRequestReader::Headers(request) => Ok(req_headers(request.headers())),
-    HttpRequestReader::Body(headers, _) => Ok(req_headers(headers)),
+    HttpRequestReader::Headers(request) => {
+      Ok(req_headers(request.headers(), separate_cookies))
+    }
+    HttpRequestReader::Body(headers, _) => {
+      Ok(req_headers(headers, separate_cookies))
+    }
     _ => unreachable!(),
   }
 }
 
-fn http_response(
-  data: Option<StringOrBuffer>,
-  compressing: bool,
-  encoding: Encoding,
-) -> Result<(HttpResponseWriter, hyper_v014::Body), AnyError> {
-  // Gzip, after level 1, doesn't produce significant size difference.
-  // This default matches nginx default gzip compression level (1):
-  // https://nginx.org/en/docs/http/ngx_http_gzip_module.html#gzip_comp_level
+/// Returns the values of a single request header, in the order they were
+/// received, so that handlers that only need one header can skip building
+/// all of them. `name` is case-insensitive. `Cookie` values are joined like
+/// in `op_http_headers`, unless the connection uses `separateCookies`.
+#[op2]
+#[serde]
+fn op_http_request_header(
+  state: &mut OpState,
+  #[smi] rid: u32,
+  #[string] name: String,
+) -> Result<Vec<ByteString>, AnyError> {
+  let stream = state.resource_table.get::<HttpStreamReadResource>(rid)?;
+  let rd = RcRef::map(&stream, |r| &r.rd)
+    .try_borrow()
+    .ok_or_else(|| http_error("already in use"))?;
+  let name = HeaderName::from_bytes(name.as_bytes())?;
+  let separate_cookies = stream.conn.options.separate_cookies;
+  match &*rd {
+    HttpRequestReader::Headers(request) => {
+      Ok(req_header(request.headers(), &name, separate_cookies))
+    }
+    HttpRequestReader::Body(headers, _) => {
+      Ok(req_header(headers, &name, separate_cookies))
+    }
+    HttpRequestReader::Closed => Err(http_error("request already consumed")),
+  }
+}
+
+fn req_header(
+  header_map: &HeaderMap<HeaderValue>,
+  name: &HeaderName,
+  separate_cookies: bool,
+) -> Vec<ByteString> {
+  let values = header_map
+    .get_all(name)
+    .iter()
+    .map(|value| value.as_bytes());
+  if name == hyper_v014::header::COOKIE && !separate_cookies {
+    let cookies = values.collect::<Vec<_>>();
+    if cookies.is_empty() {
+      return vec![];
+    }
+    return vec![cookies.join("; ".as_bytes()).into()];
+  }
+  values.map(ByteString::from).collect()
+}
+
+/// Reports whether the request body has been read to the end, so it no longer
+/// holds up the next request on the connection.
+#[op2(fast)]
+fn op_http_body_consumed(
+  state: &mut OpState,
+  #[smi] rid: ResourceId,
+) -> Result<bool, AnyError> {
+  let stream = state.resource_table.get::<HttpStreamReadResource>(rid)?;
+  stream.body_consumed()
+}
+
+/// Reads and discards the rest of the request body. The usual body size
+/// limit and read timeout still apply.
+#[op2(async)]
+async fn op_http_drain_body(
+  state: Rc<RefCell<OpState>>,
+  #[smi] rid: ResourceId,
+) -> Result<(), AnyError> {
+  let stream = state
+    .borrow()
+    .resource_table
+    .get::<HttpStreamReadResource>(rid)?;
+  while !stream.clone().read(64 * 1024).await?.is_empty() {} // 64KB
+  Ok(())
+}
+
+/// Returns the trailing headers sent after the request body, or an empty list
+/// if there were none. Fails if the body has not been read to the end yet.
+/// Only HTTP/2 requests can carry trailers; hyper drops them from chunked
+/// HTTP/1.1 bodies.
+#[op2(async)]
+#[serde]
+async fn op_http_request_trailers(
+  state: Rc<RefCell<OpState>>,
+  #[smi] rid: ResourceId,
+) -> Result<Vec<(ByteString, ByteString)>, AnyError> {
+  let stream = state
+    .borrow()
+    .resource_table
+    .get::<HttpStreamReadResource>(rid)?;
+  match stream.trailers().await? {
+    Some(trailers) => Ok(req_headers(&trailers, true)),
+    None => Ok(vec![]),
+  }
+}
+
+/// A `multipart/form-data` request body that is split into parts as it is
+/// read. Parts are read in order: moving on to the next part discards
+/// whatever is left of the current one.
+struct HttpMultipartResource {
+  body: Rc<HttpStreamReadResource>,
+  reader: AsyncRefCell<MultipartReader>,
+  // The number of parts returned so far. Only the latest one is readable.
+  parts: Cell<u64>,
+}
+
+struct MultipartReader {
+  parser: MultipartParser,
+  // An event that was read but is not for the reader that read it.
+  pending: Option<MultipartEvent>,
+}
+
+impl MultipartReader {
+  async fn next_event(
+    &mut self,
+    body: &Rc<HttpStreamReadResource>,
+  ) -> Result<MultipartEvent, AnyError> {
+    if let Some(event) = self.pending.take() {
+      return Ok(event);
+    }
+    loop {
+      if let Some(event) = self.parser.next_event()? {
+        return Ok(event);
+      }
+      let chunk = body.clone().read(64 * 1024).await?; // 64KB
+      if chunk.is_empty() {
+        self.parser.finish()?;
+        return Ok(MultipartEvent::End);
+      }
+      self.parser.feed(&chunk);
+    }
+  }
+}
+
+impl HttpMultipartResource {
+  /// Skips to the start of the next part and returns its headers.
+  async fn next_part(
+    self: &Rc<Self>,
+  ) -> Result<Option<Vec<(ByteString, ByteString)>>, AnyError> {
+    let mut reader = RcRef::map(self, |r| &r.reader).borrow_mut().await;
+    loop {
+      match reader.next_event(&self.body).await? {
+        MultipartEvent::Part(headers) => {
+          self.parts.set(self.parts.get() + 1);
+          let headers = headers
+            .into_iter()
+            .map(|(name, value)| (name.into(), value.into()))
+            .collect();
+          return Ok(Some(headers));
+        }
+        MultipartEvent::Data(_) => {}
+        MultipartEvent::End => {
+          reader.pending = Some(MultipartEvent::End);
+          return Ok(None);
+        }
+      }
+    }
+  }
+}
+
+impl Resource for HttpMultipartResource {
+  fn name(&self) -> Cow<str> {
+    "httpMultipart".into()
+  }
+}
+
+/// The body of one part of a multipart request body.
+struct HttpMultipartPartResource {
+  multipart: Rc<HttpMultipartResource>,
+  part: u64,
+}
+
+impl Resource for HttpMultipartPartResource {
+  fn name(&self) -> Cow<str> {
+    "httpMultipartPart".into()
+  }
+
+  /// Reads the next chunk of the part's body. Once a later part has been
+  /// requested, the part reads as ended.
+  fn read(self: Rc<Self>, limit: usize) -> AsyncResult<BufView> {
+    Box::pin(async move {
+      let multipart = &self.multipart;
+      let mut reader = RcRef::map(multipart, |r| &r.reader).borrow_mut().await;
+      if multipart.parts.get() != self.part {
+        return Ok(BufView::empty());
+      }
+      match reader.next_event(&multipart.body).await? {
+        MultipartEvent::Data(mut data) => {
+          if data.len() > limit {
+            let rest = data.split_off(limit);
+            reader.pending = Some(MultipartEvent::Data(rest));
+          }
+          Ok(BufView::from(data))
+        }
+        event => {
+          reader.pending = Some(event);
+          Ok(BufView::empty())
+        }
+      }
+    })
+  }
+}
+
+/// Starts splitting the request body into the parts of a `multipart/form-data`
+/// body, using the `boundary` parameter of its `Content-Type`. The request
+/// body must not be read by other means afterwards.
+#[op2(fast)]
+#[smi]
+fn op_http_multipart(
+  state: &mut OpState,
+  #[smi] rid: ResourceId,
+  #[string] boundary: String,
+) -> Result<ResourceId, AnyError> {
+  // RFC 2046, section 5.1.1.
+  if boundary.is_empty() || boundary.len() > 70 {
+    return Err(type_error("invalid multipart boundary"));
+  }
+  let body = state.resource_table.get::<HttpStreamReadResource>(rid)?;
+  let multipart = HttpMultipartResource {
+    body,
+    reader: AsyncRefCell::new(MultipartReader {
+      parser: MultipartParser::new(&boundary),
+      pending: None,
+    }),
+    parts: Cell::new(0),
+  };
+  Ok(state.resource_table.add(multipart))
+}
+
+/// Returns the headers of the next part in a multipart body and a readable
+/// resource for its body, or `null` after the last part. Whatever is left of
+/// the previous part's body is skipped.
+#[op2(async)]
+#[serde]
+async fn op_http_multipart_next(
+  state: Rc<RefCell<OpState>>,
+  #[smi] rid: ResourceId,
+) -> Result<Option<(Vec<(ByteString, ByteString)>, ResourceId)>, AnyError> {
+  let multipart = state
+    .borrow()
+    .resource_table
+    .get::<HttpMultipartResource>(rid)?;
+  let Some(headers) = multipart.next_part().await? else {
+    return Ok(None);
+  };
+  let part = HttpMultipartPartResource {
+    part: multipart.parts.get(),
+    multipart,
+  };
+  let part_rid = state.borrow_mut().resource_table.add(part);
+  Ok(Some((headers, part_rid)))
+}
+
+fn http_response(
+  data: Option<StringOrBuffer>,
+  compressing: bool,
+  encoding: Encoding,
+  options: &HttpConnOptions,
+) -> Result<(HttpResponseWriter, hyper_v014::Body), AnyError> {
+  // Gzip, after level 1, doesn't produce significant size difference.
+  // This default matches nginx default gzip compression level (1):
+  // https://nginx.org/en/docs/http/ngx_http_gzip_module.html#gzip_comp_level
   const GZIP_DEFAULT_COMPRESSION_LEVEL: u8 = 1;
+  // lgwin 22 is equivalent to brotli window size of (2**22)-16 bytes (~4MB)
+  let brotli_window = options.brotli_window.unwrap_or(22);
 
   match data {
     Some(data) if compressing => match encoding {
@@ -753,9 +2876,13 @@ fn http_response(
         // quality level 6 is based on google's nginx default value for
         // on-the-fly compression
         // https://github.com/google/ngx_brotli#brotli_comp_level
-        // lgwin 22 is equivalent to brotli window size of (2**22)-16 bytes
-        // (~4MB)
-        let mut writer = brotli::CompressorWriter::new(Vec::new(), 4096, 6, 22);
+        let quality = options.brotli_quality.unwrap_or(6);
+        let mut writer = brotli::CompressorWriter::new(
+          Vec::new(),
+          4096,
+          quality,
+          brotli_window,
+        );
         writer.write_all(&data)?;
         Ok((HttpResponseWriter::Closed, writer.into_inner().into()))
       }
@@ -767,12 +2894,26 @@ fn http_response(
         writer.write_all(&data)?;
         Ok((HttpResponseWriter::Closed, writer.finish()?.into()))
       }
+      // The "deflate" content coding is a zlib stream (RFC 9110, 8.4.1.2).
+      Encoding::Deflate => {
+        let mut writer = ZlibWriter::new(
+          Vec::new(),
+          Compression::new(GZIP_DEFAULT_COMPRESSION_LEVEL.into()),
+        );
+        writer.write_all(&data)?;
+        Ok((HttpResponseWriter::Closed, writer.finish()?.into()))
+      }
       _ => unreachable!(), // forbidden by accepts_compression
     },
     Some(data) => {
       // If a buffer was passed, but isn't compressible, we use it to
-      // construct a response body.
-      Ok((HttpResponseWriter::Closed, data.to_vec().into()))
+      // construct a response body. The buffer's backing store is shared with
+      // the body rather than copied.
+      let bytes = match data {
+        StringOrBuffer::Buffer(buf) => Bytes::from(BufView::from(buf)),
+        StringOrBuffer::String(string) => Bytes::from(string.into_bytes()),
+      };
+      Ok((HttpResponseWriter::Closed, bytes.into()))
     }
     None if compressing => {
       // Create a one way pipe that implements tokio's async io traits. To do
@@ -783,12 +2924,24 @@ fn http_response(
       let (_, writer) = tokio::io::split(b);
       let writer: Pin<Box<dyn tokio::io::AsyncWrite>> = match encoding {
         Encoding::Brotli => {
-          Box::pin(BrotliEncoder::with_quality(writer, Level::Fastest))
+          let level = match options.brotli_quality {
+            Some(quality) => Level::Precise(quality as i32),
+            None => Level::Fastest,
+          };
+          let params =
+            EncoderParams::default().window_size(brotli_window as i32);
+          Box::pin(BrotliEncoder::with_quality_and_params(
+            writer, level, params,
+          ))
         }
         Encoding::Gzip => Box::pin(GzipEncoder::with_quality(
           writer,
           Level::Precise(GZIP_DEFAULT_COMPRESSION_LEVEL.into()),
         )),
+        Encoding::Deflate => Box::pin(ZlibEncoder::with_quality(
+          writer,
+          Level::Precise(GZIP_DEFAULT_COMPRESSION_LEVEL.into()),
+        )),
         _ => unreachable!(), // forbidden by accepts_compression
       };
       let (stream, shutdown_handle) =
@@ -803,10 +2956,46 @@ fn http_response(
     }
     None => {
       let (body_tx, body_rx) = Body::channel();
-      Ok((
-        HttpResponseWriter::BodyUncompressed(body_tx.into()),
-        body_rx,
-      ))
+      let mut body_tx = BodyUncompressedSender::from(body_tx);
+      body_tx.buffer_size = options.write_buffer_size.unwrap_or(0);
+      Ok((HttpResponseWriter::BodyUncompressed(body_tx), body_rx))
+    }
+  }
+}
+
+/// Adds headers given by JS to `hmap`, failing with a `TypeError` that names
+/// the offending header if a name or value is invalid. Values may not contain
+/// control characters other than tabs, which rules out header injection with
+/// line breaks.
+fn append_headers(
+  hmap: &mut HeaderMap,
+  headers: Vec<(ByteString, ByteString)>,
+) -> Result<(), AnyError> {
+  for (k, v) in headers {
+    let name = HeaderName::try_from(k.as_slice()).map_err(|_| {
+      let name = String::from_utf8_lossy(&k);
+      type_error(format!("Invalid header name: {name:?}"))
+    })?;
+    let v: Vec<u8> = v.into();
+    let value = HeaderValue::try_from(v).map_err(|_| {
+      type_error(format!("Invalid value for header \"{name}\""))
+    })?;
+    hmap.append(name, value);
+  }
+  Ok(())
+}
+
+// Headers set by the handler win over the connection's defaults, including
+// every value of a repeated default header.
+fn merge_default_headers(
+  hmap: &mut hyper_v014::HeaderMap,
+  defaults: &hyper_v014::HeaderMap,
+) {
+  for name in defaults.keys() {
+    if !hmap.contains_key(name) {
+      for value in defaults.get_all(name) {
+        hmap.append(name.clone(), value.clone());
+      }
     }
   }
 }
@@ -843,6 +3032,22 @@ fn ensure_vary_accept_encoding(hmap: &mut hyper_v014::HeaderMap) {
   );
 }
 
+// Proxies would hold back Server-Sent Events if they could cache them.
+fn ensure_event_stream_no_cache(hmap: &mut hyper_v014::HeaderMap) {
+  let is_event_stream = hmap
+    .get(hyper_v014::header::CONTENT_TYPE)
+    .and_then(|v| v.to_str().ok())
+    .and_then(|v| v.parse::<mime::Mime>().ok())
+    .map(|v| v.essence_str() == "text/event-stream")
+    .unwrap_or_default();
+  if is_event_stream && !hmap.contains_key(hyper_v014::header::CACHE_CONTROL) {
+    hmap.insert(
+      hyper_v014::header::CACHE_CONTROL,
+      HeaderValue::from_static("no-cache"),
+    );
+  }
+}
+
 fn should_compress(headers: &hyper_v014::HeaderMap) -> bool {
   // skip compression if the cache-control header value is set to "no-transform" or not utf8
   fn cache_control_no_transform(
@@ -873,6 +3078,9 @@ fn should_compress(headers: &hyper_v014::HeaderMap) -> bool {
       .unwrap_or_default()
 }
 
+/// Pipes the readable resource `stream` into the response body without a
+/// round trip through JavaScript per chunk. The next chunk is only read once
+/// the connection has accepted the previous one.
 #[op2(async)]
 async fn op_http_write_resource(
   state: Rc<RefCell<OpState>>,
@@ -890,6 +3098,7 @@ async fn op_http_write_resource(
       HttpResponseWriter::Headers(_) => {
         return Err(http_error("no response headers"))
       }
+      HttpResponseWriter::Closed if http_stream.is_head => return Ok(()),
       HttpResponseWriter::Closed => {
         return Err(http_error("response already completed"))
       }
@@ -903,27 +3112,44 @@ async fn op_http_write_resource(
 
     match &mut *wr {
       HttpResponseWriter::Body { writer, .. } => {
-        let mut result = writer.write_all(&view).await;
-        if result.is_ok() {
-          result = writer.flush().await;
-        }
-        if let Err(err) = result {
-          assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
-          // Don't return "broken pipe", that's an implementation detail.
-          // Pull up the failure associated with the transport connection instead.
-          http_stream.conn.closed().await?;
-          // If there was no connection error, drop body_tx.
-          *wr = HttpResponseWriter::Closed;
+        let result = write_with_timeout(&http_stream, async {
+          writer.write_all(&view).await?;
+          writer.flush().await
+        })
+        .await?;
+        match result {
+          Ok(_) => HttpConnStats::add(
+            &http_stream.conn.stats.bytes_written,
+            view.len(),
+          ),
+          Err(err) => {
+            assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
+            // Don't return "broken pipe", that's an implementation detail.
+            // Pull up the failure associated with the transport connection instead.
+            http_stream.conn.closed().await?;
+            // If there was no connection error, drop body_tx.
+            *wr = HttpResponseWriter::Closed;
+          }
         }
       }
       HttpResponseWriter::BodyUncompressed(body) => {
-        let bytes = view.to_vec().into();
-        if let Err(err) = body.sender().send_data(bytes).await {
-          assert!(err.is_closed());
-          // Pull up the failure associated with the transport connection instead.
-          http_stream.conn.closed().await?;
-          // If there was no connection error, drop body_tx.
-          *wr = HttpResponseWriter::Closed;
+        body.reserve(view.len())?;
+        let Some(bytes) = body.buffered(Bytes::from(view)) else {
+          continue;
+        };
+        let len = bytes.len();
+        let send_fut = body.sender().send_data(bytes);
+        match write_with_timeout(&http_stream, send_fut).await? {
+          Ok(_) => {
+            HttpConnStats::add(&http_stream.conn.stats.bytes_written, len)
+          }
+          Err(err) => {
+            assert!(err.is_closed());
+            // Pull up the failure associated with the transport connection instead.
+            http_stream.conn.closed().await?;
+            // If there was no connection error, drop body_tx.
+            *wr = HttpResponseWriter::Closed;
+          }
         }
       }
       _ => unreachable!(),
@@ -932,11 +3158,67 @@ async fn op_http_write_resource(
   Ok(())
 }
 
+/// Writes a chunk of a streamed response body. Resolves to `false` if the
+/// write had to wait for the client to consume earlier chunks, so producers
+/// know to slow down.
 #[op2(async)]
 async fn op_http_write(
   state: Rc<RefCell<OpState>>,
   #[smi] rid: ResourceId,
   #[buffer] buf: JsBuffer,
+) -> Result<bool, AnyError> {
+  let stream = state
+    .borrow()
+    .resource_table
+    .get::<HttpStreamWriteResource>(rid)?;
+  http_write(&stream, Bytes::from(BufView::from(buf))).await
+}
+
+/// Writes the last chunk of a streamed response body and ends the body, in
+/// one op instead of `op_http_write` followed by `op_http_shutdown`.
+#[op2(async)]
+async fn op_http_write_end(
+  state: Rc<RefCell<OpState>>,
+  #[smi] rid: ResourceId,
+  #[buffer] buf: JsBuffer,
+) -> Result<(), AnyError> {
+  let stream = state
+    .borrow()
+    .resource_table
+    .get::<HttpStreamWriteResource>(rid)?;
+  if !buf.is_empty() {
+    http_write(&stream, Bytes::from(BufView::from(buf))).await?;
+  }
+  shutdown_response(&stream).await
+}
+
+/// Writes several chunks of a streamed response body as one, saving an op
+/// call and a body frame per chunk. Resolves like `op_http_write`.
+#[op2(async)]
+async fn op_http_write_vectored(
+  state: Rc<RefCell<OpState>>,
+  #[smi] rid: ResourceId,
+  #[serde] bufs: Vec<JsBuffer>,
+) -> Result<bool, AnyError> {
+  let stream = state
+    .borrow()
+    .resource_table
+    .get::<HttpStreamWriteResource>(rid)?;
+  let mut bytes =
+    BytesMut::with_capacity(bufs.iter().map(|buf| buf.len()).sum());
+  for buf in &bufs {
+    bytes.extend_from_slice(buf);
+  }
+  http_write(&stream, bytes.freeze()).await
+}
+
+/// Waits until the chunks written so far have been handed to the connection,
+/// including those held back by `writeBufferSize`. For compressed bodies this
+/// also flushes the encoder.
+#[op2(async)]
+async fn op_http_flush(
+  state: Rc<RefCell<OpState>>,
+  #[smi] rid: ResourceId,
 ) -> Result<(), AnyError> {
   let stream = state
     .borrow()
@@ -946,14 +3228,128 @@ async fn op_http_write(
 
   match &mut *wr {
     HttpResponseWriter::Headers(_) => Err(http_error("no response headers")),
+    HttpResponseWriter::Closed if stream.is_head => Ok(()),
     HttpResponseWriter::Closed => Err(http_error("response already completed")),
-    HttpResponseWriter::Body { writer, .. } => {
-      let mut result = writer.write_all(&buf).await;
-      if result.is_ok() {
-        result = writer.flush().await;
+    HttpResponseWriter::Body { writer, .. } => match writer.flush().await {
+      Ok(_) => Ok(()),
+      Err(err) => {
+        assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
+        stream.conn.closed().await?;
+        *wr = HttpResponseWriter::Closed;
+        Err(http_error("response already completed"))
       }
-      match result {
+    },
+    HttpResponseWriter::BodyUncompressed(body) => {
+      // The body channel has room again once hyper took the pending chunk.
+      let result = async {
+        body.flush_buffer(&stream.conn.stats).await?;
+        poll_fn(|cx| body.sender().poll_ready(cx)).await
+      };
+      match result.await {
         Ok(_) => Ok(()),
+        Err(err) => {
+          assert!(err.is_closed());
+          stream.conn.closed().await?;
+          *wr = HttpResponseWriter::Closed;
+          Err(http_error("response already completed"))
+        }
+      }
+    }
+  }
+}
+
+/// A Server-Sent Events message. A message with only a `comment` can be used
+/// as a heartbeat that clients ignore.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct SseMessage {
+  event: Option<String>,
+  data: Option<String>,
+  id: Option<String>,
+  retry: Option<u32>,
+  comment: Option<String>,
+}
+
+/// Splits a field value into lines. SSE treats CRLF, a lone CR and a lone LF
+/// as line endings.
+fn sse_lines(value: &str) -> impl Iterator<Item = &str> {
+  value
+    .split("\r\n")
+    .flat_map(|line| line.split(['\r', '\n']))
+}
+
+fn sse_frame(message: &SseMessage) -> Result<Vec<u8>, AnyError> {
+  let mut frame = String::new();
+  if let Some(comment) = &message.comment {
+    for line in sse_lines(comment) {
+      frame.push_str(": ");
+      frame.push_str(line);
+      frame.push('\n');
+    }
+  }
+  for (field, value) in [("event", &message.event), ("id", &message.id)] {
+    if let Some(value) = value {
+      if value.contains(['\r', '\n', '\0']) {
+        return Err(custom_error("Http", format!("invalid SSE {field}")));
+      }
+      frame.push_str(&format!("{field}: {value}\n"));
+    }
+  }
+  if let Some(retry) = message.retry {
+    frame.push_str(&format!("retry: {retry}\n"));
+  }
+  if let Some(data) = &message.data {
+    for line in sse_lines(data) {
+      frame.push_str("data: ");
+      frame.push_str(line);
+      frame.push('\n');
+    }
+  }
+  frame.push('\n');
+  Ok(frame.into_bytes())
+}
+
+/// Writes a framed Server-Sent Events message to a streamed response body.
+/// Reports backpressure like `op_http_write`.
+#[op2(async)]
+async fn op_http_write_sse(
+  state: Rc<RefCell<OpState>>,
+  #[smi] rid: ResourceId,
+  #[serde] message: SseMessage,
+) -> Result<bool, AnyError> {
+  let stream = state
+    .borrow()
+    .resource_table
+    .get::<HttpStreamWriteResource>(rid)?;
+  let frame = sse_frame(&message)?;
+  http_write(&stream, Bytes::from(frame)).await
+}
+
+async fn http_write(
+  stream: &Rc<HttpStreamWriteResource>,
+  bytes: Bytes,
+) -> Result<bool, AnyError> {
+  let mut wr = RcRef::map(stream, |r| &r.wr).borrow_mut().await;
+  let len = bytes.len();
+
+  match &mut *wr {
+    HttpResponseWriter::Headers(_) => Err(http_error("no response headers")),
+    HttpResponseWriter::Closed if stream.is_head => Ok(true),
+    HttpResponseWriter::Closed => Err(http_error("response already completed")),
+    HttpResponseWriter::Body { writer, .. } => {
+      let (result, waited) = write_with_timeout(
+        stream,
+        poll_with_backpressure(async {
+          writer.write_all(&bytes).await?;
+          writer.flush().await
+        }),
+      )
+      .await?;
+      match result {
+        Ok(_) => {
+          HttpConnStats::add(&stream.conn.stats.bytes_written, len);
+          Ok(!waited)
+        }
         Err(err) => {
           assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
           // Don't return "broken pipe", that's an implementation detail.
@@ -966,9 +3362,22 @@ async fn op_http_write(
       }
     }
     HttpResponseWriter::BodyUncompressed(body) => {
-      let bytes = Bytes::from(buf.to_vec());
-      match body.sender().send_data(bytes).await {
-        Ok(_) => Ok(()),
+      body.reserve(len)?;
+      let Some(bytes) = body.buffered(bytes) else {
+        return Ok(true);
+      };
+      // Includes the chunks held back in the write buffer before this one.
+      let flushed = bytes.len();
+      let (result, waited) = write_with_timeout(
+        stream,
+        poll_with_backpressure(body.sender().send_data(bytes)),
+      )
+      .await?;
+      match result {
+        Ok(_) => {
+          HttpConnStats::add(&stream.conn.stats.bytes_written, flushed);
+          Ok(!waited)
+        }
         Err(err) => {
           assert!(err.is_closed());
           // Pull up the failure associated with the transport connection instead.
@@ -982,6 +3391,410 @@ async fn op_http_write(
   }
 }
 
+/// Drives a response body write, aborting the connection if the client does
+/// not accept the data within the `body_write_timeout`.
+async fn write_with_timeout<F: Future>(
+  stream: &HttpStreamWriteResource,
+  fut: F,
+) -> Result<F::Output, AnyError> {
+  let Some(timeout) = stream.conn.options.body_write_timeout else {
+    return Ok(fut.await);
+  };
+  match tokio::time::timeout(timeout, fut).await {
+    Ok(output) => Ok(output),
+    Err(_) => {
+      stream.conn.abort_handle.cancel();
+      Err(custom_error("TimedOut", "response body write timed out"))
+    }
+  }
+}
+
+/// Drives `fut` to completion and also reports whether it ever had to wait,
+/// which for body writes means the client is consuming slower than we write.
+async fn poll_with_backpressure<F: Future>(fut: F) -> (F::Output, bool) {
+  let mut fut = pin!(fut);
+  let mut waited = false;
+  let output = poll_fn(|cx| {
+    let poll = fut.as_mut().poll(cx);
+    waited |= poll.is_pending();
+    poll
+  })
+  .await;
+  (output, waited)
+}
+
+/// The part of a file selected by a `Range` request header.
+#[derive(Debug, PartialEq, Eq)]
+enum ByteRange {
+  /// No (usable) range was requested; serve the whole file.
+  Full,
+  /// Serve the inclusive byte range `start..=end`.
+  Partial { start: u64, end: u64 },
+  /// The range starts beyond the end of the file.
+  Unsatisfiable,
+}
+
+/// Parses a single `bytes=` range against a file of `size` bytes. Ranges that
+/// can't be parsed and multiple ranges are ignored, as RFC 9110 allows.
+fn parse_byte_range(range: Option<&str>, size: u64) -> ByteRange {
+  let Some(spec) = range.and_then(|r| r.trim().strip_prefix("bytes=")) else {
+    return ByteRange::Full;
+  };
+  let Some((start, end)) = spec.trim().split_once('-') else {
+    return ByteRange::Full;
+  };
+  let last = size.checked_sub(1);
+  match (start.parse::<u64>(), end.parse::<u64>()) {
+    // bytes=-N: the last N bytes.
+    _ if start.is_empty() => match (end.parse::<u64>(), last) {
+      (Ok(0), _) | (Ok(_), None) => ByteRange::Unsatisfiable,
+      (Ok(suffix), Some(last)) => ByteRange::Partial {
+        start: size.saturating_sub(suffix),
+        end: last,
+      },
+      (Err(_), _) => ByteRange::Full,
+    },
+    // bytes=N-: everything from N on.
+    (Ok(start), _) if end.is_empty() => match last {
+      Some(last) if start <= last => ByteRange::Partial { start, end: last },
+      _ => ByteRange::Unsatisfiable,
+    },
+    (Ok(start), Ok(end)) if start <= end => match last {
+      Some(last) if start <= last => ByteRange::Partial {
+        start,
+        end: min(end, last),
+      },
+      _ => ByteRange::Unsatisfiable,
+    },
+    _ => ByteRange::Full,
+  }
+}
+
+/// Responds with the contents of the file resource `file_rid`, streamed
+/// directly from Rust. `range` is the request's `Range` header: a single byte
+/// range is answered with `206 Partial Content`, and a range past the end of
+/// the file with `416 Range Not Satisfiable`.
+#[op2(async)]
+async fn op_http_serve_file(
+  state: Rc<RefCell<OpState>>,
+  #[smi] rid: ResourceId,
+  #[smi] file_rid: ResourceId,
+  #[serde] headers: Vec<(ByteString, ByteString)>,
+  #[string] range: Option<String>,
+) -> Result<(), AnyError> {
+  let stream = state
+    .borrow()
+    .resource_table
+    .get::<HttpStreamWriteResource>(rid)?;
+  let file = FileResource::get_file(&state.borrow(), file_rid)?;
+  let size = file.clone().stat_async().await?.size;
+
+  let mut builder = Response::builder();
+  // SAFETY: can not fail, since a fresh Builder is non-errored
+  let hmap = unsafe { builder.headers_mut().unwrap_unchecked() };
+  hmap.reserve(headers.len() + 3);
+  append_headers(hmap, headers)?;
+  merge_default_headers(hmap, &stream.conn.options.default_headers);
+  hmap.insert(
+    hyper_v014::header::ACCEPT_RANGES,
+    HeaderValue::from_static("bytes"),
+  );
+
+  let (status, start, len) = match parse_byte_range(range.as_deref(), size) {
+    ByteRange::Full => (200, 0, size),
+    ByteRange::Partial { start, end } => {
+      let content_range = format!("bytes {start}-{end}/{size}");
+      hmap.insert(
+        hyper_v014::header::CONTENT_RANGE,
+        HeaderValue::try_from(content_range)?,
+      );
+      (206, start, end - start + 1)
+    }
+    ByteRange::Unsatisfiable => {
+      let content_range = format!("bytes */{size}");
+      hmap.insert(
+        hyper_v014::header::CONTENT_RANGE,
+        HeaderValue::try_from(content_range)?,
+      );
+      (416, 0, 0)
+    }
+  };
+  hmap.insert(hyper_v014::header::CONTENT_LENGTH, HeaderValue::from(len));
+
+  if stream.is_head {
+    let response = builder.status(status).body(Body::empty())?;
+    return send_response(&stream, response, HttpResponseWriter::Closed).await;
+  }
+
+  // Seek even from the start of the file, since the resource may have been
+  // read from already, and before the headers go out so that a failure can
+  // still be reported as an error.
+  file.clone().seek_async(io::SeekFrom::Start(start)).await?;
+  let (mut body_tx, body_rx) = Body::channel();
+  let response = builder.status(status).body(body_rx)?;
+  send_response(&stream, response, HttpResponseWriter::Closed).await?;
+
+  let mut remaining = len;
+  while remaining > 0 {
+    let limit = min(remaining, 64 * 1024) as usize; // 64KB
+    let view = file.clone().read(limit).await?;
+    if view.is_empty() {
+      // The file was truncated while it was being sent.
+      body_tx.abort();
+      return Err(http_error("file ended before the response was complete"));
+    }
+    remaining = remaining.saturating_sub(view.len() as u64);
+    let len = view.len();
+    let send_fut = body_tx.send_data(Bytes::from(view));
+    if let Err(err) = write_with_timeout(&stream, send_fut).await? {
+      assert!(err.is_closed());
+      // Pull up the failure associated with the transport connection instead.
+      stream.conn.closed().await?;
+      return Err(http_error("response already completed"));
+    }
+    HttpConnStats::add(&stream.conn.stats.bytes_written, len);
+  }
+  Ok(())
+}
+
+/// Sends trailing headers after a streamed response body, which also closes the
+/// write half of the HTTP stream. Only uncompressed bodies can carry trailers.
+/// Hyper drops trailers on HTTP/1 connections, so the op fails there and
+/// leaves the response to be ended with `op_http_shutdown`.
+#[op2(async)]
+async fn op_http_write_trailers(
+  state: Rc<RefCell<OpState>>,
+  #[smi] rid: ResourceId,
+  #[serde] trailers: Vec<(ByteString, ByteString)>,
+) -> Result<(), AnyError> {
+  let stream = state
+    .borrow()
+    .resource_table
+    .get::<HttpStreamWriteResource>(rid)?;
+  let mut wr = RcRef::map(&stream, |r| &r.wr).borrow_mut().await;
+
+  match &*wr {
+    HttpResponseWriter::Headers(_) => {
+      return Err(http_error("no response headers"))
+    }
+    HttpResponseWriter::Closed => {
+      return Err(http_error("response already completed"))
+    }
+    HttpResponseWriter::Body { .. } => {
+      return Err(http_error("compressed responses cannot have trailers"))
+    }
+    HttpResponseWriter::BodyUncompressed(_) => {}
+  };
+  if !stream.is_http2 {
+    return Err(http_error("trailers can only be sent over HTTP/2"));
+  }
+
+  let mut trailer_map = HeaderMap::with_capacity(trailers.len());
+  append_headers(&mut trailer_map, trailers)?;
+
+  let HttpResponseWriter::BodyUncompressed(mut body) = take(&mut *wr) else {
+    unreachable!();
+  };
+  let result = async {
+    body.flush_buffer(&stream.conn.stats).await?;
+    body.sender().send_trailers(trailer_map).await
+  };
+  let result = result.await;
+  body.shutdown()?;
+  match result {
+    Ok(_) => Ok(()),
+    Err(err) => {
+      assert!(err.is_closed());
+      // Pull up the failure associated with the transport connection instead.
+      stream.conn.closed().await?;
+      Err(http_error("response already completed"))
+    }
+  }
+}
+
+/// Sends a complete response in one op: the head, the whole `body` and the
+/// `trailers`, after which the write half of the HTTP stream is closed. The
+/// trailers are queued together with the body, so they always follow it. The
+/// body is never compressed, as compressed responses cannot have trailers.
+/// Trailers can only be sent over HTTP/2; over HTTP/1 the op fails before
+/// anything is written.
+#[op2(async)]
+async fn op_http_write_full(
+  state: Rc<RefCell<OpState>>,
+  #[smi] rid: ResourceId,
+  #[smi] status: u16,
+  #[serde] headers: Vec<(ByteString, ByteString)>,
+  #[buffer] body: JsBuffer,
+  #[serde] trailers: Vec<(ByteString, ByteString)>,
+) -> Result<(), AnyError> {
+  let stream = state
+    .borrow()
+    .resource_table
+    .get::<HttpStreamWriteResource>(rid)?;
+  let body = Bytes::from(BufView::from(body));
+  write_full(&stream, status, headers, body, trailers).await
+}
+
+async fn write_full(
+  stream: &Rc<HttpStreamWriteResource>,
+  status: u16,
+  headers: Vec<(ByteString, ByteString)>,
+  body: Bytes,
+  trailers: Vec<(ByteString, ByteString)>,
+) -> Result<(), AnyError> {
+  // Hyper would drop them silently.
+  if !stream.is_http2 && !trailers.is_empty() {
+    return Err(http_error("trailers can only be sent over HTTP/2"));
+  }
+  let mut builder = Response::builder().status(status);
+  // SAFETY: can not fail, since a fresh Builder is non-errored
+  let hmap = unsafe { builder.headers_mut().unwrap_unchecked() };
+  hmap.reserve(headers.len() + 1);
+  append_headers(hmap, headers)?;
+  merge_default_headers(hmap, &stream.conn.options.default_headers);
+  ensure_event_stream_no_cache(hmap);
+
+  let mut trailer_map = HeaderMap::with_capacity(trailers.len());
+  append_headers(&mut trailer_map, trailers)?;
+
+  if stream.is_head {
+    if !hmap.contains_key(hyper_v014::header::CONTENT_LENGTH) {
+      hmap.insert(
+        hyper_v014::header::CONTENT_LENGTH,
+        HeaderValue::from(body.len()),
+      );
+    }
+    let response = builder.body(Body::empty())?;
+    return send_response(stream, response, HttpResponseWriter::Closed).await;
+  }
+
+  let len = body.len();
+  // Hyper trusts the declared length, see `op_http_write_headers`.
+  if content_length(hmap).is_some_and(|content_length| {
+    content_length != len as u64 && status != StatusCode::NOT_MODIFIED.as_u16()
+  }) {
+    return Err(http_error(
+      "response body does not match its Content-Length",
+    ));
+  }
+  let body = body_with_trailers(body, trailer_map);
+  let response = builder.body(body)?;
+  send_response(stream, response, HttpResponseWriter::Closed).await?;
+  HttpConnStats::add(&stream.conn.stats.bytes_written, len);
+  Ok(())
+}
+
+/// Returns a body that yields `data` and then `trailers`.
+fn body_with_trailers(data: Bytes, trailers: HeaderMap) -> Body {
+  let (mut sender, body) = Body::channel();
+  // A fresh channel always has room for one chunk, and the trailers slot
+  // cannot be closed while `body` is alive.
+  if !data.is_empty() {
+    sender.try_send_data(data).unwrap();
+  }
+  sender
+    .send_trailers(trailers)
+    .now_or_never()
+    .unwrap()
+    .unwrap();
+  body
+}
+
+/// Abandons the response on the HTTP stream, whether or not its headers have
+/// been sent. On HTTP/2 only this stream is reset, and the other requests on
+/// the connection carry on. Hyper always resets with `INTERNAL_ERROR`, so no
+/// other error code can be given. HTTP/1 has no way to abort one response, so
+/// the connection is closed instead.
+#[op2(async)]
+async fn op_http_reset(
+  state: Rc<RefCell<OpState>>,
+  #[smi] rid: ResourceId,
+) -> Result<(), AnyError> {
+  let stream = state
+    .borrow()
+    .resource_table
+    .get::<HttpStreamWriteResource>(rid)?;
+  reset_response(&stream).await;
+  Ok(())
+}
+
+async fn reset_response(stream: &Rc<HttpStreamWriteResource>) {
+  let mut wr = RcRef::map(stream, |r| &r.wr).borrow_mut().await;
+  if !stream.is_http2 {
+    stream.conn.abort_handle.cancel();
+  }
+  // Dropping the response sender, or a body writer that was not shut down,
+  // fails the response, and hyper resets the stream.
+  drop(take(&mut *wr));
+}
+
+/// Sends the response with HTTP/1.0 framing, for clients and test harnesses
+/// that do not understand chunked encoding: the status line says `HTTP/1.0`,
+/// a streamed body is ended by closing the connection, and the connection is
+/// closed after the response in any case. `Transfer-Encoding` headers are
+/// dropped. This has to be called before the response headers are sent, and
+/// is not available on HTTP/2.
+#[op2(fast)]
+fn op_http_response_http10(
+  state: &mut OpState,
+  #[smi] rid: ResourceId,
+) -> Result<(), AnyError> {
+  let stream = state.resource_table.get::<HttpStreamWriteResource>(rid)?;
+  response_http10(&stream)
+}
+
+fn response_http10(
+  stream: &Rc<HttpStreamWriteResource>,
+) -> Result<(), AnyError> {
+  if stream.is_http2 {
+    return Err(http_error("HTTP/1.0 framing is not available on HTTP/2"));
+  }
+  let wr = RcRef::map(stream, |r| &r.wr).try_borrow();
+  if !matches!(wr.as_deref(), Some(HttpResponseWriter::Headers(_))) {
+    return Err(http_error("response headers already sent"));
+  }
+  stream.http10.set(true);
+  Ok(())
+}
+
+/// Fails a response whose body is being written, so that the client sees an
+/// incomplete transfer instead of a body that ended normally. On HTTP/2 the
+/// stream is reset; on HTTP/1 the connection is closed without ending the
+/// body, ie: without the last chunk of a chunked body, or short of its
+/// `Content-Length`. Chunks that hyper has not flushed yet may be lost.
+///
+/// Unlike `op_http_reset`, this requires the response headers to have been
+/// sent.
+#[op2(async)]
+async fn op_http_response_abort(
+  state: Rc<RefCell<OpState>>,
+  #[smi] rid: ResourceId,
+) -> Result<(), AnyError> {
+  let stream = state
+    .borrow()
+    .resource_table
+    .get::<HttpStreamWriteResource>(rid)?;
+  abort_response(&stream).await
+}
+
+async fn abort_response(
+  stream: &Rc<HttpStreamWriteResource>,
+) -> Result<(), AnyError> {
+  let mut wr = RcRef::map(stream, |r| &r.wr).borrow_mut().await;
+  match &*wr {
+    HttpResponseWriter::Headers(_) => Err(http_error("no response headers")),
+    HttpResponseWriter::Closed => Err(http_error("response already completed")),
+    // Dropping the sender aborts the body, and dropping the compressing
+    // writer before its shutdown handle is used makes the body stream fail.
+    // Either way hyper gives up on the response.
+    HttpResponseWriter::Body { .. }
+    | HttpResponseWriter::BodyUncompressed(_) => {
+      drop(take(&mut *wr));
+      Ok(())
+    }
+  }
+}
+
 /// Gracefully closes the write half of the HTTP stream. Note that this does not
 /// remove the HTTP stream resource from the resource table; it still has to be
 /// closed with `Deno.core.close()`.
@@ -989,73 +3802,296 @@ async fn op_http_write(
 async fn op_http_shutdown(
   state: Rc<RefCell<OpState>>,
   #[smi] rid: ResourceId,
-) -> Result<(), AnyError> {
+) -> Result<(), AnyError> {
+  let stream = state
+    .borrow()
+    .resource_table
+    .get::<HttpStreamWriteResource>(rid)?;
+  shutdown_response(&stream).await
+}
+
+async fn shutdown_response(
+  stream: &Rc<HttpStreamWriteResource>,
+) -> Result<(), AnyError> {
+  let mut wr = RcRef::map(stream, |r| &r.wr).borrow_mut().await;
+  let wr = take(&mut *wr);
+  match wr {
+    HttpResponseWriter::Body {
+      mut writer,
+      shutdown_handle,
+    } => {
+      shutdown_handle.shutdown();
+      match writer.shutdown().await {
+        Ok(_) => {}
+        Err(err) => {
+          assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
+          // Don't return "broken pipe", that's an implementation detail.
+          // Pull up the failure associated with the transport connection instead.
+          stream.conn.closed().await?;
+        }
+      }
+    }
+    HttpResponseWriter::BodyUncompressed(mut body) => {
+      if let Err(err) = body.flush_buffer(&stream.conn.stats).await {
+        assert!(err.is_closed());
+        // Pull up the failure associated with the transport connection instead.
+        stream.conn.closed().await?;
+        // The connection is fine, but this stream is gone, eg: it was reset.
+        return Err(http_error("response body could not be flushed"));
+      }
+      body.shutdown()?;
+    }
+    _ => {}
+  };
+  Ok(())
+}
+
+#[op2]
+#[string]
+fn op_http_websocket_accept_header(
+  #[string] key: String,
+) -> Result<String, AnyError> {
+  let digest = ring::digest::digest(
+    &ring::digest::SHA1_FOR_LEGACY_USE_ONLY,
+    format!("{key}258EAFA5-E914-47DA-95CA-C5AB0DC85B11").as_bytes(),
+  );
+  Ok(BASE64_STANDARD.encode(digest))
+}
+
+/// The subprotocol a handshake response selected, if any.
+fn websocket_protocol(headers: &HeaderMap) -> Option<String> {
+  let protocol = headers.get(hyper_v014::header::SEC_WEBSOCKET_PROTOCOL)?;
+  Some(protocol.to_str().ok()?.trim().to_string())
+}
+
+/// What an upgraded WebSocket negotiated, for `WebSocket.protocol` and
+/// `WebSocket.extensions`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct HttpWebSocketUpgrade {
+  rid: ResourceId,
+  /// The `Sec-WebSocket-Protocol` of the `101` response, or `""`.
+  protocol: String,
+  /// Always `""`, as no extension is ever accepted.
+  extensions: String,
+}
+
+/// Hands the transport of an upgraded request over to a server WebSocket,
+/// returning its resource id along with the negotiated subprotocol and
+/// extensions.
+///
+/// `max_frame_size` bounds the payload of each frame received from the
+/// client and `max_message_size` the size of a message across its frames.
+/// Larger frames or messages close the WebSocket with an error.
+///
+/// The `101` response has already been sent when this runs, so a failure here
+/// can only close the connection. Requests that cannot be upgraded are
+/// answered with a `400` by `op_http_write_headers` instead of the `101`.
+#[op2(async)]
+#[serde]
+async fn op_http_upgrade_websocket(
+  state: Rc<RefCell<OpState>>,
+  #[smi] rid: ResourceId,
+  #[serde] max_message_size: Option<usize>,
+  #[serde] max_frame_size: Option<usize>,
+) -> Result<HttpWebSocketUpgrade, AnyError> {
+  let stream = state
+    .borrow_mut()
+    .resource_table
+    .get::<HttpStreamReadResource>(rid)?;
+  let mut rd = RcRef::map(&stream, |r| &r.rd).borrow_mut().await;
+
+  let request = match &mut *rd {
+    HttpRequestReader::Headers(request) => request,
+    _ => {
+      return Err(http_error("cannot upgrade because request body was used"))
+    }
+  };
+
+  let (transport, bytes) =
+    extract_network_stream(hyper_v014::upgrade::on(request).await?);
+  let ws_rid = ws_create_server_stream(
+    &mut state.borrow_mut(),
+    transport,
+    bytes,
+    max_message_size,
+    max_frame_size,
+  )?;
+  let protocol = stream.upgrade.websocket_protocol.borrow_mut().take();
+  Ok(HttpWebSocketUpgrade {
+    rid: ws_rid,
+    protocol: protocol.unwrap_or_default(),
+    extensions: String::new(),
+  })
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct HttpUpgradeResult {
+  conn_rid: ResourceId,
+  conn_type: &'static str,
+  read_buf: ToJsBuffer,
+}
+
+/// Takes over the transport of an upgraded request (eg: `CONNECT` or a
+/// `101 Switching Protocols` response) and returns it as a plain TCP, TLS or
+/// Unix connection resource. Bytes that hyper already read past the request
+/// are returned in `readBuf` and must be consumed before reading the
+/// connection.
+#[op2(async)]
+#[serde]
+async fn op_http_upgrade(
+  state: Rc<RefCell<OpState>>,
+  #[smi] rid: ResourceId,
+) -> Result<HttpUpgradeResult, AnyError> {
   let stream = state
-    .borrow()
+    .borrow_mut()
     .resource_table
-    .get::<HttpStreamWriteResource>(rid)?;
-  let mut wr = RcRef::map(&stream, |r| &r.wr).borrow_mut().await;
-  let wr = take(&mut *wr);
-  match wr {
-    HttpResponseWriter::Body {
-      mut writer,
-      shutdown_handle,
-    } => {
-      shutdown_handle.shutdown();
-      match writer.shutdown().await {
-        Ok(_) => {}
-        Err(err) => {
-          assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
-          // Don't return "broken pipe", that's an implementation detail.
-          // Pull up the failure associated with the transport connection instead.
-          stream.conn.closed().await?;
-        }
-      }
-    }
-    HttpResponseWriter::BodyUncompressed(body) => {
-      body.shutdown();
+    .get::<HttpStreamReadResource>(rid)?;
+  let mut rd = RcRef::map(&stream, |r| &r.rd).borrow_mut().await;
+
+  let request = match &mut *rd {
+    HttpRequestReader::Headers(request) => request,
+    _ => {
+      return Err(http_error("cannot upgrade because request body was used"))
     }
-    _ => {}
   };
-  Ok(())
+
+  let (transport, bytes) =
+    extract_network_stream(hyper_v014::upgrade::on(request).await?);
+  let mut state = state.borrow_mut();
+  let (conn_rid, conn_type) = match transport {
+    NetworkStream::Tcp(stream) => (
+      state
+        .resource_table
+        .add(TcpStreamResource::new(stream.into_split())),
+      "tcp",
+    ),
+    NetworkStream::Tls(stream) => (
+      state
+        .resource_table
+        .add(TlsStreamResource::new(stream.into_split())),
+      "tls",
+    ),
+    #[cfg(unix)]
+    NetworkStream::Unix(stream) => (
+      state
+        .resource_table
+        .add(deno_net::io::UnixStreamResource::new(stream.into_split())),
+      "unix",
+    ),
+  };
+  Ok(HttpUpgradeResult {
+    conn_rid,
+    conn_type,
+    read_buf: bytes.to_vec().into(),
+  })
 }
 
-#[op2]
-#[string]
-fn op_http_websocket_accept_header(
-  #[string] key: String,
-) -> Result<String, AnyError> {
-  let digest = ring::digest::digest(
-    &ring::digest::SHA1_FOR_LEGACY_USE_ONLY,
-    format!("{key}258EAFA5-E914-47DA-95CA-C5AB0DC85B11").as_bytes(),
-  );
-  Ok(BASE64_STANDARD.encode(digest))
+/// The stream of an HTTP/2 extended CONNECT request, taken over as a tunnel.
+/// Shutting it down ends the stream; the connection stays open.
+struct HttpTunnelResource {
+  rd: AsyncRefCell<tokio::io::ReadHalf<hyper_v014::upgrade::Upgraded>>,
+  wr: AsyncRefCell<tokio::io::WriteHalf<hyper_v014::upgrade::Upgraded>>,
+  cancel_handle: CancelHandle,
+}
+
+impl HttpTunnelResource {
+  fn new(upgraded: hyper_v014::upgrade::Upgraded) -> Self {
+    let (rd, wr) = tokio::io::split(upgraded);
+    Self {
+      rd: rd.into(),
+      wr: wr.into(),
+      cancel_handle: CancelHandle::new(),
+    }
+  }
+
+  async fn read(self: Rc<Self>, buf: &mut [u8]) -> Result<usize, AnyError> {
+    let cancel_handle = RcRef::map(&self, |r| &r.cancel_handle);
+    let mut rd = RcRef::map(&self, |r| &r.rd).borrow_mut().await;
+    Ok(rd.read(buf).try_or_cancel(cancel_handle).await?)
+  }
+
+  async fn write(self: Rc<Self>, buf: &[u8]) -> Result<usize, AnyError> {
+    let cancel_handle = RcRef::map(&self, |r| &r.cancel_handle);
+    let mut wr = RcRef::map(&self, |r| &r.wr).borrow_mut().await;
+    Ok(wr.write(buf).try_or_cancel(cancel_handle).await?)
+  }
 }
 
+impl Resource for HttpTunnelResource {
+  fn name(&self) -> Cow<str> {
+    "httpTunnel".into()
+  }
+
+  deno_core::impl_readable_byob!();
+  deno_core::impl_writable!();
+
+  fn shutdown(self: Rc<Self>) -> AsyncResult<()> {
+    Box::pin(async move {
+      let mut wr = RcRef::map(&self, |r| &r.wr).borrow_mut().await;
+      Ok(wr.shutdown().await?)
+    })
+  }
+
+  fn close(self: Rc<Self>) {
+    self.cancel_handle.cancel();
+  }
+}
+
+/// Takes over an HTTP/2 extended CONNECT request (RFC 8441) for `protocol`,
+/// eg: `connect-udp`, and returns its stream as a duplex resource. The
+/// connection must have been served with `http2EnableConnectProtocol`, and the
+/// request's `:protocol` pseudo-header must be `protocol`.
+///
+/// Like `op_http_upgrade`, this resolves once a 2XX response without a body
+/// has been sent. Other requests on the connection are unaffected.
 #[op2(async)]
 #[smi]
-async fn op_http_upgrade_websocket(
+async fn op_http_upgrade_connect(
   state: Rc<RefCell<OpState>>,
   #[smi] rid: ResourceId,
+  #[string] protocol: String,
 ) -> Result<ResourceId, AnyError> {
   let stream = state
     .borrow_mut()
     .resource_table
     .get::<HttpStreamReadResource>(rid)?;
-  let mut rd = RcRef::map(&stream, |r| &r.rd).borrow_mut().await;
+  let tunnel = upgrade_connect(&stream, &protocol).await?;
+  Ok(state.borrow_mut().resource_table.add(tunnel))
+}
 
+async fn upgrade_connect(
+  stream: &Rc<HttpStreamReadResource>,
+  protocol: &str,
+) -> Result<HttpTunnelResource, AnyError> {
+  if !stream.conn.options.http2_enable_connect_protocol {
+    return Err(http_error("extended CONNECT is not enabled"));
+  }
+  let mut rd = RcRef::map(stream, |r| &r.rd).borrow_mut().await;
   let request = match &mut *rd {
     HttpRequestReader::Headers(request) => request,
     _ => {
       return Err(http_error("cannot upgrade because request body was used"))
     }
   };
-
-  let (transport, bytes) =
-    extract_network_stream(hyper_v014::upgrade::on(request).await?);
-  let ws_rid =
-    ws_create_server_stream(&mut state.borrow_mut(), transport, bytes)?;
-  Ok(ws_rid)
+  // h2 only accepts a `:protocol` pseudo-header once the setting was sent.
+  let requested = request.extensions().get::<hyper_v014::ext::Protocol>();
+  match requested {
+    Some(requested) if requested.as_str() == protocol => {}
+    Some(requested) => {
+      return Err(custom_error(
+        "Http",
+        format!(
+          "expected protocol {protocol:?}, got {:?}",
+          requested.as_str()
+        ),
+      ))
+    }
+    None => return Err(http_error("not an extended CONNECT request")),
+  }
+  let upgraded = hyper_v014::upgrade::on(request).await?;
+  Ok(HttpTunnelResource::new(upgraded))
 }
 
 // Needed so hyper can use non Send futures
@@ -1086,27 +4122,55 @@ fn http_error(message: &'static str) -> AnyError {
   custom_error("Http", message)
 }
 
-/// Filters out the ever-surprising 'shutdown ENOTCONN' errors.
-fn filter_enotconn(
-  result: Result<(), hyper_v014::Error>,
-) -> Result<(), hyper_v014::Error> {
-  if result
-    .as_ref()
-    .err()
-    .and_then(|err| err.source())
+/// A `Deno.errors.RequestBodyTooLarge`, which extends `Deno.errors.Http`, so
+/// handlers can tell an oversized body from other request failures.
+fn body_too_large_error() -> AnyError {
+  custom_error("RequestBodyTooLarge", "request body too large")
+}
+
+/// Returns the class of the `Deno.errors` error that a connection error is
+/// surfaced to JS as, so a client disconnect, a stalled client and a broken
+/// request can be told apart. TLS failures surface as `InvalidData`.
+fn hyper_error_class(err: &hyper_v014::Error) -> &'static str {
+  if err.is_timeout() {
+    return "TimedOut";
+  }
+  let kind = err
+    .source()
     .and_then(|err| err.downcast_ref::<io::Error>())
-    .filter(|err| err.kind() == io::ErrorKind::NotConnected)
-    .is_some()
-  {
-    Ok(())
-  } else {
-    result
+    .map(|err| err.kind());
+  match kind {
+    Some(io::ErrorKind::NotConnected) => "NotConnected",
+    Some(io::ErrorKind::ConnectionReset) => "ConnectionReset",
+    Some(io::ErrorKind::ConnectionAborted) => "ConnectionAborted",
+    Some(io::ErrorKind::BrokenPipe) => "BrokenPipe",
+    Some(io::ErrorKind::TimedOut) => "TimedOut",
+    Some(io::ErrorKind::UnexpectedEof) => "UnexpectedEof",
+    Some(io::ErrorKind::InvalidData) => "InvalidData",
+    _ => "Http",
   }
 }
 
-/// Create a future that is forever pending.
-fn never() -> Pending<Never> {
-  pending()
+fn hyper_error(err: &hyper_v014::Error) -> AnyError {
+  custom_error(hyper_error_class(err), err.to_string())
+}
+
+/// Filters out the ever-surprising 'shutdown ENOTCONN' errors, as well as the
+/// errors caused by clients that abruptly close the connection.
+fn filter_disconnect_errors(
+  result: Result<(), hyper_v014::Error>,
+) -> Result<(), hyper_v014::Error> {
+  match result {
+    Err(err)
+      if matches!(
+        hyper_error_class(&err),
+        "NotConnected" | "ConnectionReset" | "ConnectionAborted" | "BrokenPipe"
+      ) =>
+    {
+      Ok(())
+    }
+    result => result,
+  }
 }
 
 trait CanDowncastUpgrade: Sized {
@@ -1197,3 +4261,714 @@ fn extract_network_stream<U: CanDowncastUpgrade>(
   drop(upgraded);
   unreachable!("unexpected stream type");
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use flate2::read::GzDecoder;
+  use flate2::read::ZlibDecoder;
+  use std::convert::Infallible;
+  use std::io::Read;
+
+  fn headers(entries: &[(&'static str, &'static str)]) -> HeaderMap {
+    let mut hmap = HeaderMap::new();
+    for (k, v) in entries {
+      hmap.append(*k, HeaderValue::from_static(v));
+    }
+    hmap
+  }
+
+  #[test]
+  fn should_compress_compressible_content_type() {
+    assert!(should_compress(&headers(&[("content-type", "text/plain")])));
+    assert!(should_compress(&headers(&[(
+      "content-type",
+      "application/json; charset=utf-8"
+    )])));
+  }
+
+  #[test]
+  fn should_not_compress_precompressed_body() {
+    assert!(!should_compress(&headers(&[])));
+    assert!(!should_compress(&headers(&[("content-type", "image/png")])));
+    assert!(!should_compress(&headers(&[
+      ("content-type", "text/plain"),
+      ("content-encoding", "gzip"),
+    ])));
+    assert!(!should_compress(&headers(&[
+      ("content-type", "text/plain"),
+      ("content-range", "bytes 0-9/100"),
+    ])));
+    assert!(!should_compress(&headers(&[
+      ("content-type", "text/plain"),
+      ("cache-control", "no-transform"),
+    ])));
+  }
+
+  #[tokio::test]
+  async fn http_response_gzip() {
+    let data = "hello world ".repeat(32);
+    let (wr, body) = http_response(
+      Some(StringOrBuffer::String(data.clone())),
+      true,
+      Encoding::Gzip,
+      &HttpConnOptions::default(),
+    )
+    .unwrap();
+    assert!(matches!(wr, HttpResponseWriter::Closed));
+
+    let compressed = hyper_v014::body::to_bytes(body).await.unwrap();
+    let mut decompressed = String::new();
+    GzDecoder::new(&compressed[..])
+      .read_to_string(&mut decompressed)
+      .unwrap();
+    assert_eq!(decompressed, data);
+  }
+
+  #[tokio::test]
+  async fn http_response_deflate_stream() {
+    let (wr, body) =
+      http_response(None, true, Encoding::Deflate, &HttpConnOptions::default())
+        .unwrap();
+    let HttpResponseWriter::Body {
+      mut writer,
+      shutdown_handle,
+    } = wr
+    else {
+      unreachable!();
+    };
+    let chunks = deno_core::unsync::spawn(hyper_v014::body::to_bytes(body));
+    writer.write_all(b"hello ").await.unwrap();
+    writer.flush().await.unwrap();
+    writer.write_all(b"world").await.unwrap();
+    shutdown_handle.shutdown();
+    writer.shutdown().await.unwrap();
+
+    let compressed = chunks.await.unwrap().unwrap();
+    let mut decompressed = String::new();
+    ZlibDecoder::new(&compressed[..])
+      .read_to_string(&mut decompressed)
+      .unwrap();
+    assert_eq!(decompressed, "hello world");
+  }
+
+  #[tokio::test]
+  async fn http_response_deflate_empty_stream() {
+    let (wr, body) =
+      http_response(None, true, Encoding::Deflate, &HttpConnOptions::default())
+        .unwrap();
+    let HttpResponseWriter::Body {
+      mut writer,
+      shutdown_handle,
+    } = wr
+    else {
+      unreachable!();
+    };
+    let chunks = deno_core::unsync::spawn(hyper_v014::body::to_bytes(body));
+    shutdown_handle.shutdown();
+    writer.shutdown().await.unwrap();
+
+    let compressed = chunks.await.unwrap().unwrap();
+    let mut decompressed = Vec::new();
+    ZlibDecoder::new(&compressed[..])
+      .read_to_end(&mut decompressed)
+      .unwrap();
+    assert!(decompressed.is_empty());
+  }
+
+  #[tokio::test]
+  async fn http_response_brotli_options() {
+    let data = "hello world ".repeat(32);
+    let options = HttpConnOptions {
+      brotli_quality: Some(11),
+      brotli_window: Some(16),
+      ..Default::default()
+    };
+    let (_, body) = http_response(
+      Some(StringOrBuffer::String(data.clone())),
+      true,
+      Encoding::Brotli,
+      &options,
+    )
+    .unwrap();
+
+    let compressed = hyper_v014::body::to_bytes(body).await.unwrap();
+    let mut decompressed = String::new();
+    brotli::Decompressor::new(&compressed[..], 4096)
+      .read_to_string(&mut decompressed)
+      .unwrap();
+    assert_eq!(decompressed, data);
+  }
+
+  #[test]
+  fn req_headers_cookies() {
+    let hmap =
+      headers(&[("cookie", "a=1"), ("accept", "*/*"), ("cookie", "b=2")]);
+    let merged: Vec<(ByteString, ByteString)> = vec![
+      ("accept".into(), "*/*".into()),
+      ("cookie".into(), "a=1; b=2".into()),
+    ];
+    assert_eq!(req_headers(&hmap, false), merged);
+    let separate: Vec<(ByteString, ByteString)> = vec![
+      ("cookie".into(), "a=1".into()),
+      ("cookie".into(), "b=2".into()),
+      ("accept".into(), "*/*".into()),
+    ];
+    assert_eq!(req_headers(&hmap, true), separate);
+  }
+
+  #[test]
+  fn append_headers_rejects_invalid() {
+    let mut hmap = HeaderMap::new();
+    append_headers(&mut hmap, vec![("x-a".into(), "1\tb".into())]).unwrap();
+    assert_eq!(hmap["x-a"], "1\tb");
+
+    let err =
+      append_headers(&mut hmap, vec![("x\nb".into(), "1".into())]).unwrap_err();
+    assert_eq!(err.to_string(), "Invalid header name: \"x\\nb\"");
+    assert_eq!(
+      deno_core::error::get_custom_error_class(&err),
+      Some("TypeError")
+    );
+    let injected = vec![("x-c".into(), "1\r\nset-cookie: a=1".into())];
+    let err = append_headers(&mut hmap, injected).unwrap_err();
+    assert_eq!(err.to_string(), "Invalid value for header \"x-c\"");
+    assert!(!hmap.contains_key("x-c"));
+  }
+
+  #[test]
+  fn header_transforms() {
+    let transforms: Vec<HttpHeaderTransform> = deno_core::serde_json::from_str(
+      r#"[
+        { "type": "rename", "from": "x-real-ip", "to": "x-client-ip" },
+        { "type": "default", "name": "x-request-id", "value": "none" },
+        { "type": "default", "name": "accept", "value": "*/*" },
+        { "type": "strip", "name": "x-debug" }
+      ]"#,
+    )
+    .unwrap();
+    let mut hmap = headers(&[
+      ("x-real-ip", "192.0.2.1"),
+      ("x-client-ip", "192.0.2.2"),
+      ("accept", "text/html"),
+      ("x-debug", "1"),
+    ]);
+    transform_headers(&mut hmap, &transforms);
+    assert_eq!(
+      hmap,
+      headers(&[
+        ("x-client-ip", "192.0.2.2"),
+        ("x-client-ip", "192.0.2.1"),
+        ("accept", "text/html"),
+        ("x-request-id", "none"),
+      ])
+    );
+  }
+
+  #[test]
+  fn req_header_values() {
+    let hmap = headers(&[("cookie", "a=1"), ("x-id", "1"), ("cookie", "b=2")]);
+    let name = HeaderName::from_static("cookie");
+    let merged: Vec<ByteString> = vec!["a=1; b=2".into()];
+    assert_eq!(req_header(&hmap, &name, false), merged);
+    let separate: Vec<ByteString> = vec!["a=1".into(), "b=2".into()];
+    assert_eq!(req_header(&hmap, &name, true), separate);
+    let name = HeaderName::from_static("x-id");
+    assert_eq!(req_header(&hmap, &name, false), vec![ByteString::from("1")]);
+    let name = HeaderName::from_static("host");
+    assert!(req_header(&hmap, &name, false).is_empty());
+  }
+
+  /// Transport that yields `reads` to the server in order, followed by EOF,
+  /// and accepts all writes.
+  struct MockIo {
+    reads: Vec<Result<&'static [u8], io::ErrorKind>>,
+  }
+
+  impl AsyncRead for MockIo {
+    fn poll_read(
+      mut self: Pin<&mut Self>,
+      _cx: &mut Context<'_>,
+      buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+      if !self.reads.is_empty() {
+        match self.reads.remove(0) {
+          Ok(bytes) => buf.put_slice(bytes),
+          Err(kind) => return Poll::Ready(Err(kind.into())),
+        }
+      }
+      Poll::Ready(Ok(()))
+    }
+  }
+
+  impl AsyncWrite for MockIo {
+    fn poll_write(
+      self: Pin<&mut Self>,
+      _cx: &mut Context<'_>,
+      buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+      Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(
+      self: Pin<&mut Self>,
+      _cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+      Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(
+      self: Pin<&mut Self>,
+      _cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+      Poll::Ready(Ok(()))
+    }
+  }
+
+  /// Serves a connection whose client sends the start of a request and then
+  /// fails with `error`.
+  async fn serve_mock(
+    error: Result<&'static [u8], io::ErrorKind>,
+  ) -> Result<(), hyper_v014::Error> {
+    let io = MockIo {
+      reads: vec![Ok(b"GET / HTTP/1.1\r\n"), error],
+    };
+    let service = hyper_v014::service::service_fn(|_| async {
+      Ok::<_, Infallible>(Response::new(Body::empty()))
+    });
+    let result = Http::new().http1_only(true).serve_connection(io, service);
+    filter_disconnect_errors(result.await)
+  }
+
+  #[test]
+  fn req_target_forms() {
+    use hyper_v014::Version;
+    let req = |version, uri| {
+      let req = Request::builder().version(version).uri(uri);
+      req_target(&req.body(Body::empty()).unwrap())
+    };
+    assert_eq!(req(Version::HTTP_11, "/a?b"), "/a?b");
+    assert_eq!(
+      req(Version::HTTP_11, "http://example.com/a?b"),
+      "http://example.com/a?b"
+    );
+    assert_eq!(req(Version::HTTP_11, "example.com:443"), "example.com:443");
+    assert_eq!(req(Version::HTTP_11, "*"), "*");
+    assert_eq!(req(Version::HTTP_2, "https://example.com/a?b"), "/a?b");
+  }
+
+  #[test]
+  fn req_keep_alive_by_version() {
+    use hyper_v014::Version;
+    let req = |version, connection: Option<&str>| {
+      let mut builder = Request::builder().version(version);
+      if let Some(connection) = connection {
+        builder = builder.header("connection", connection);
+      }
+      req_keep_alive(&builder.body(Body::empty()).unwrap())
+    };
+    assert!(req(Version::HTTP_11, None));
+    assert!(!req(Version::HTTP_11, Some("Upgrade, Close")));
+    assert!(!req(Version::HTTP_10, None));
+    assert!(req(Version::HTTP_10, Some("Keep-Alive")));
+    assert!(req(Version::HTTP_2, Some("close")));
+  }
+
+  #[test]
+  fn request_limit() {
+    let limit = HttpRequestLimit::new(NonZeroUsize::new(1));
+    let permit = limit.acquire().now_or_never().unwrap();
+    assert!(permit.is_some());
+    let mut next = pin!(limit.acquire());
+    assert!(next.as_mut().now_or_never().is_none());
+    drop(permit);
+    assert!(next.now_or_never().unwrap().is_some());
+
+    let unlimited = HttpRequestLimit::new(None);
+    assert!(unlimited.acquire().now_or_never().unwrap().is_none());
+  }
+
+  #[test]
+  fn request_limit_clamped() {
+    let limit = HttpRequestLimit::new(NonZeroUsize::new(usize::MAX));
+    let semaphore = limit.0.unwrap();
+    assert_eq!(semaphore.available_permits(), Semaphore::MAX_PERMITS);
+  }
+
+  /// Serves a loopback TCP connection, returning the client end. JS can't set
+  /// the request limit, so unlike the rest of the connection behaviour, which
+  /// `tests/unit/http_test.ts` covers, it is tested here.
+  async fn serve_tcp() -> (TcpStream, Rc<HttpConnResource>) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (client_io, (server_io, _)) =
+      tokio::try_join!(TcpStream::connect(addr), listener.accept()).unwrap();
+    let conn = Rc::new(HttpConnResource::new(
+      NetworkStream::Tcp(server_io),
+      "http",
+      addr.into(),
+      HttpConnOptions::default(),
+      HttpConnCount::default().acquire(),
+    ));
+    (client_io, conn)
+  }
+
+  #[tokio::test]
+  async fn request_limit_ignores_idle_conns() {
+    let limit = HttpRequestLimit::new(NonZeroUsize::new(1));
+    let (_idle_io, idle_conn) = serve_tcp().await;
+    let mut idle_accept = pin!(accept_limited(&idle_conn, &limit));
+    assert!(idle_accept.as_mut().now_or_never().is_none());
+
+    let request = b"GET / HTTP/1.1\r\n\r\n";
+    let (mut io_a, conn_a) = serve_tcp().await;
+    io_a.write_all(request).await.unwrap();
+    let (_read_a, write_a, ..) =
+      accept_limited(&conn_a, &limit).await.unwrap().unwrap();
+
+    // A second request waits for the first response, not for the idle
+    // connection.
+    let (mut io_b, conn_b) = serve_tcp().await;
+    io_b.write_all(request).await.unwrap();
+    let mut accept_b = pin!(accept_limited(&conn_b, &limit));
+    let wait = Duration::from_millis(50);
+    assert!(tokio::time::timeout(wait, accept_b.as_mut()).await.is_err());
+    drop(write_a);
+    assert!(accept_b.await.unwrap().is_some());
+    assert!(idle_accept.now_or_never().is_none());
+  }
+
+  #[test]
+  fn token_bucket() {
+    let mut bucket = TokenBucket::new(2.0, 3.0);
+    let start = bucket.refilled_at;
+    assert!((0..3).all(|_| bucket.try_take(start)));
+    assert!(!bucket.try_take(start));
+    // Refills at 2 per second.
+    assert!(!bucket.try_take(start + Duration::from_millis(400)));
+    assert!(bucket.try_take(start + Duration::from_millis(500)));
+    assert!(!bucket.try_take(start + Duration::from_millis(500)));
+    // Never holds more than the burst.
+    let later = start + Duration::from_secs(60);
+    assert!((0..3).all(|_| bucket.try_take(later)));
+    assert!(!bucket.try_take(later));
+  }
+
+  #[tokio::test]
+  async fn filter_disconnect_errors_ignores_disconnects() {
+    for kind in [
+      io::ErrorKind::NotConnected,
+      io::ErrorKind::ConnectionReset,
+      io::ErrorKind::ConnectionAborted,
+      io::ErrorKind::BrokenPipe,
+    ] {
+      assert!(serve_mock(Err(kind)).await.is_ok(), "{kind:?}");
+    }
+  }
+
+  #[tokio::test]
+  async fn filter_disconnect_errors_keeps_other_errors() {
+    assert!(serve_mock(Err(io::ErrorKind::InvalidData)).await.is_err());
+    assert!(serve_mock(Ok(b"NOT A HEADER\r\n\r\n")).await.is_err());
+  }
+
+  #[tokio::test]
+  async fn hyper_error_classes() {
+    let class = |result: Result<(), hyper_v014::Error>| {
+      hyper_error_class(&result.unwrap_err())
+    };
+    assert_eq!(
+      class(serve_mock(Err(io::ErrorKind::InvalidData)).await),
+      "InvalidData"
+    );
+    assert_eq!(
+      class(serve_mock(Err(io::ErrorKind::TimedOut)).await),
+      "TimedOut"
+    );
+    assert_eq!(class(serve_mock(Ok(b"NOT A HEADER\r\n\r\n")).await), "Http");
+  }
+
+  #[test]
+  fn forwarded_scheme_from_headers() {
+    let scheme = |entries| forwarded_scheme(&headers(entries));
+    assert_eq!(
+      scheme(&[("forwarded", "for=192.0.2.1;proto=https")]),
+      Some("https")
+    );
+    assert_eq!(
+      scheme(&[("forwarded", "Proto=\"HTTPS\", proto=http")]),
+      Some("https")
+    );
+    assert_eq!(
+      scheme(&[("x-forwarded-proto", "https, http")]),
+      Some("https")
+    );
+    assert_eq!(
+      scheme(&[("forwarded", "proto=http"), ("x-forwarded-proto", "https")]),
+      Some("http")
+    );
+    assert_eq!(scheme(&[("x-forwarded-proto", "wss")]), None);
+    assert_eq!(scheme(&[]), None);
+  }
+
+  #[test]
+  fn websocket_protocol_from_response() {
+    assert_eq!(
+      websocket_protocol(&headers(&[("sec-websocket-protocol", " chat ")])),
+      Some("chat".to_string())
+    );
+    assert_eq!(websocket_protocol(&headers(&[])), None);
+  }
+
+  #[tokio::test]
+  async fn write_buffer_coalesces_chunks() {
+    let (body_tx, mut body_rx) = Body::channel();
+    let mut body = BodyUncompressedSender::from(body_tx);
+    body.buffer_size = 8;
+    assert_eq!(body.buffered(Bytes::from("abc")), None);
+    assert_eq!(body.buffered(Bytes::from("def")), None);
+    assert_eq!(body.buffered(Bytes::from("ghi")).unwrap(), "abcdefghi");
+    // Large chunks skip the empty buffer.
+    assert_eq!(body.buffered(Bytes::from("0123456789")).unwrap().len(), 10);
+
+    assert_eq!(body.buffered(Bytes::from("jk")), None);
+    let stats = HttpConnStats::default();
+    body.flush_buffer(&stats).await.unwrap();
+    assert_eq!(stats.bytes_written.get(), 2);
+    body.shutdown().unwrap();
+    assert_eq!(body_rx.data().await.unwrap().unwrap(), "jk");
+    assert!(body_rx.data().await.is_none());
+  }
+
+  #[tokio::test]
+  async fn body_with_trailers_yields_data_then_trailers() {
+    let trailers = headers(&[("grpc-status", "0")]);
+    let mut body = body_with_trailers(Bytes::from("hello"), trailers.clone());
+    assert_eq!(body.data().await.unwrap().unwrap(), "hello");
+    assert!(body.data().await.is_none());
+    assert_eq!(body.trailers().await.unwrap(), Some(trailers.clone()));
+
+    let mut body = body_with_trailers(Bytes::new(), trailers.clone());
+    assert!(body.data().await.is_none());
+    assert_eq!(body.trailers().await.unwrap(), Some(trailers));
+  }
+
+  #[test]
+  fn req_url_host() {
+    let addr = HttpSocketAddr::from(
+      "127.0.0.1:8443".parse::<std::net::SocketAddr>().unwrap(),
+    );
+    let url = |uri: &str, host: Option<&'static str>| {
+      let mut builder = Request::builder().uri(uri);
+      if let Some(host) = host {
+        builder = builder.header("host", host);
+      }
+      req_url(&builder.body(Body::empty()).unwrap(), "https", &addr)
+    };
+
+    assert_eq!(
+      url("/a?b", Some("example.com:8443")),
+      "https://example.com:8443/a?b"
+    );
+    assert_eq!(url("/", Some("example.com")), "https://example.com/");
+    assert_eq!(url("/", None), "https://127.0.0.1:8443/");
+    assert_eq!(
+      url("https://example.com:8443/", None),
+      "https://example.com:8443/"
+    );
+    assert_eq!(
+      url("https://example.com:443/", None),
+      "https://example.com/"
+    );
+    assert_eq!(
+      url("https://example.com/", Some("other.com")),
+      "https://example.com/"
+    );
+  }
+
+  #[test]
+  fn sse_frame_fields() {
+    let message = SseMessage {
+      event: Some("update".into()),
+      data: Some("a\nb\r\nc".into()),
+      id: Some("1".into()),
+      retry: Some(1000),
+      ..Default::default()
+    };
+    assert_eq!(
+      sse_frame(&message).unwrap(),
+      b"event: update\nid: 1\nretry: 1000\ndata: a\ndata: b\ndata: c\n\n"
+    );
+  }
+
+  #[test]
+  fn sse_frame_heartbeat() {
+    let message = SseMessage {
+      comment: Some("ping".into()),
+      ..Default::default()
+    };
+    assert_eq!(sse_frame(&message).unwrap(), b": ping\n\n");
+  }
+
+  #[test]
+  fn sse_frame_comment_line_endings() {
+    let message = SseMessage {
+      comment: Some("x\rdata: evil".into()),
+      ..Default::default()
+    };
+    assert_eq!(sse_frame(&message).unwrap(), b": x\n: data: evil\n\n");
+  }
+
+  #[test]
+  fn sse_frame_invalid_event() {
+    let message = SseMessage {
+      event: Some("a\nb".into()),
+      ..Default::default()
+    };
+    assert!(sse_frame(&message).is_err());
+  }
+
+  #[test]
+  fn event_stream_no_cache() {
+    let mut hmap = headers(&[("content-type", "text/event-stream")]);
+    ensure_event_stream_no_cache(&mut hmap);
+    assert_eq!(hmap.get("cache-control").unwrap(), "no-cache");
+
+    let mut hmap = headers(&[
+      ("content-type", "text/event-stream; charset=utf-8"),
+      ("cache-control", "no-store"),
+    ]);
+    ensure_event_stream_no_cache(&mut hmap);
+    assert_eq!(hmap.get("cache-control").unwrap(), "no-store");
+
+    let mut hmap = headers(&[("content-type", "text/plain")]);
+    ensure_event_stream_no_cache(&mut hmap);
+    assert!(!hmap.contains_key("cache-control"));
+  }
+
+  #[tokio::test]
+  async fn poll_with_backpressure_reports_waiting() {
+    assert_eq!(poll_with_backpressure(async { 1 }).await, (1, false));
+    let fut = async {
+      tokio::task::yield_now().await;
+      2
+    };
+    assert_eq!(poll_with_backpressure(fut).await, (2, true));
+  }
+
+  #[test]
+  fn parse_byte_range_single() {
+    use ByteRange::*;
+    assert_eq!(parse_byte_range(None, 10), Full);
+    assert_eq!(
+      parse_byte_range(Some("bytes=0-4"), 10),
+      Partial { start: 0, end: 4 }
+    );
+    assert_eq!(
+      parse_byte_range(Some("bytes=5-"), 10),
+      Partial { start: 5, end: 9 }
+    );
+    assert_eq!(
+      parse_byte_range(Some("bytes=-3"), 10),
+      Partial { start: 7, end: 9 }
+    );
+    assert_eq!(
+      parse_byte_range(Some("bytes=-30"), 10),
+      Partial { start: 0, end: 9 }
+    );
+    assert_eq!(
+      parse_byte_range(Some("bytes=8-20"), 10),
+      Partial { start: 8, end: 9 }
+    );
+  }
+
+  #[test]
+  fn parse_byte_range_unsatisfiable() {
+    use ByteRange::*;
+    assert_eq!(parse_byte_range(Some("bytes=10-"), 10), Unsatisfiable);
+    assert_eq!(parse_byte_range(Some("bytes=10-12"), 10), Unsatisfiable);
+    assert_eq!(parse_byte_range(Some("bytes=-0"), 10), Unsatisfiable);
+    assert_eq!(parse_byte_range(Some("bytes=0-"), 0), Unsatisfiable);
+  }
+
+  #[test]
+  fn parse_byte_range_ignored() {
+    use ByteRange::*;
+    assert_eq!(parse_byte_range(Some("items=0-4"), 10), Full);
+    assert_eq!(parse_byte_range(Some("bytes=4-2"), 10), Full);
+    assert_eq!(parse_byte_range(Some("bytes=0-1,4-5"), 10), Full);
+    assert_eq!(parse_byte_range(Some("bytes=a-b"), 10), Full);
+    assert_eq!(parse_byte_range(Some("bytes=-"), 10), Full);
+  }
+
+  #[tokio::test]
+  async fn set_tcp_options_applies_nodelay() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let stream = TcpStream::connect(listener.local_addr().unwrap())
+      .await
+      .unwrap();
+    let options = HttpConnOptions {
+      tcp_nodelay: Some(true),
+      tcp_keepalive: Some(Duration::from_secs(30)),
+      ..Default::default()
+    };
+    set_tcp_options(&stream, &options).unwrap();
+    let socket = SockRef::from(&stream);
+    assert!(socket.nodelay().unwrap());
+    assert!(socket.keepalive().unwrap());
+  }
+
+  #[test]
+  fn merge_default_headers_keeps_handler_values() {
+    let defaults = headers(&[
+      ("server", "deno"),
+      ("x-frame-options", "DENY"),
+      ("link", "</a.css>"),
+      ("link", "</b.js>"),
+    ]);
+    let mut hmap = headers(&[("server", "custom")]);
+    merge_default_headers(&mut hmap, &defaults);
+    assert_eq!(hmap.get_all("server").iter().count(), 1);
+    assert_eq!(hmap["server"], "custom");
+    assert_eq!(hmap["x-frame-options"], "DENY");
+    assert_eq!(hmap.get_all("link").iter().count(), 2);
+  }
+
+  #[test]
+  fn req_path_and_query_pairs() {
+    let uri = "/search?q=deno+http&tag=a%26b&tag=c&empty".parse().unwrap();
+    let (path, pairs) = req_path_and_query(&uri);
+    assert_eq!(path, "/search");
+    assert_eq!(
+      pairs,
+      [
+        ("q".to_string(), "deno http".to_string()),
+        ("tag".to_string(), "a&b".to_string()),
+        ("tag".to_string(), "c".to_string()),
+        ("empty".to_string(), String::new()),
+      ]
+    );
+
+    let uri = "http://example.com".parse().unwrap();
+    assert_eq!(req_path_and_query(&uri), ("/".to_string(), vec![]));
+  }
+
+  #[tokio::test]
+  async fn body_uncompressed_content_length() {
+    let (body_tx, _body_rx) = Body::channel();
+    let mut body = BodyUncompressedSender::from(body_tx);
+    body.remaining = Some(10);
+    body.reserve(6).unwrap();
+    assert!(body.reserve(5).is_err());
+    body.reserve(4).unwrap();
+    body.shutdown().unwrap();
+
+    let (body_tx, body_rx) = Body::channel();
+    let mut body = BodyUncompressedSender::from(body_tx);
+    body.remaining = Some(10);
+    body.reserve(6).unwrap();
+    assert!(body.shutdown().is_err());
+    // The short body is aborted rather than left waiting for more data.
+    assert!(hyper_v014::body::to_bytes(body_rx).await.is_err());
+  }
+}
@@ -0,0 +1,253 @@
+// Copyright 2018-2024 the Deno authors. All rights reserved. MIT license.
+
+//! A streaming parser for `multipart/form-data` request bodies:
+//! <https://www.rfc-editor.org/rfc/rfc7578> and
+//! <https://www.rfc-editor.org/rfc/rfc2046#section-5.1.1>
+
+use bytes::Buf;
+use bytes::Bytes;
+use bytes::BytesMut;
+use deno_core::error::AnyError;
+use httparse::Status;
+use memmem::Searcher;
+use memmem::TwoWaySearcher;
+
+use crate::http_error;
+
+/// Part headers are buffered whole, so they are limited in size.
+const MAX_PART_HEADERS_SIZE: usize = 16 * 1024;
+
+/// What the parser found next in the body.
+#[derive(Debug, PartialEq)]
+pub enum MultipartEvent {
+  /// The start of a part, with its headers. Names are lowercase.
+  Part(Vec<(Vec<u8>, Vec<u8>)>),
+  /// A chunk of the current part's body.
+  Data(Bytes),
+  /// The closing delimiter. The epilogue after it is ignored.
+  End,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+  Preamble,
+  AfterDelimiter,
+  Headers,
+  Body,
+  Done,
+}
+
+pub struct MultipartParser {
+  // The boundary with the line break and dashes that precede it.
+  delimiter: Vec<u8>,
+  buf: BytesMut,
+  state: State,
+}
+
+impl MultipartParser {
+  pub fn new(boundary: &str) -> Self {
+    let delimiter = [b"\r\n--", boundary.as_bytes()].concat();
+    // The first delimiter may start the body instead of following a line
+    // break, so pretend there is one before it.
+    let buf = BytesMut::from(&b"\r\n"[..]);
+    Self {
+      delimiter,
+      buf,
+      state: State::Preamble,
+    }
+  }
+
+  /// Adds the next chunk of the body.
+  pub fn feed(&mut self, chunk: &[u8]) {
+    if self.state != State::Done {
+      self.buf.extend_from_slice(chunk);
+    }
+  }
+
+  /// Marks the end of the body, failing if the closing delimiter is missing.
+  pub fn finish(&self) -> Result<(), AnyError> {
+    match self.state {
+      State::Done => Ok(()),
+      _ => Err(http_error("multipart body ended unexpectedly")),
+    }
+  }
+
+  /// Returns the next event in the data fed so far, or `None` if more data is
+  /// needed. Body data is returned as soon as it cannot be the start of a
+  /// delimiter, so large parts are never buffered.
+  pub fn next_event(&mut self) -> Result<Option<MultipartEvent>, AnyError> {
+    loop {
+      match self.state {
+        State::Preamble => match self.find_delimiter() {
+          Some(index) => {
+            self.buf.advance(index + self.delimiter.len());
+            self.state = State::AfterDelimiter;
+          }
+          None => {
+            let discard = self.undelimited_len();
+            self.buf.advance(discard);
+            return Ok(None);
+          }
+        },
+        State::AfterDelimiter => {
+          if self.buf.len() < 2 {
+            return Ok(None);
+          }
+          if self.buf.starts_with(b"--") {
+            self.buf.clear();
+            self.state = State::Done;
+            return Ok(Some(MultipartEvent::End));
+          }
+          // The delimiter line may end with whitespace ("transport padding").
+          let Some(index) = self.buf.windows(2).position(|w| w == b"\r\n")
+          else {
+            let padding = self.buf.strip_suffix(b"\r").unwrap_or(&self.buf);
+            if padding.iter().all(|b| matches!(b, b' ' | b'\t')) {
+              return Ok(None);
+            }
+            return Err(http_error("invalid multipart delimiter"));
+          };
+          if !self.buf[..index].iter().all(|b| matches!(b, b' ' | b'\t')) {
+            return Err(http_error("invalid multipart delimiter"));
+          }
+          self.buf.advance(index + 2);
+          self.state = State::Headers;
+        }
+        State::Headers => {
+          if self.buf.starts_with(b"\r\n") {
+            self.buf.advance(2);
+            self.state = State::Body;
+            return Ok(Some(MultipartEvent::Part(vec![])));
+          }
+          let mut headers = [httparse::EMPTY_HEADER; 16];
+          match httparse::parse_headers(&self.buf, &mut headers)? {
+            Status::Complete((index, headers)) => {
+              let headers = headers
+                .iter()
+                .map(|header| {
+                  let name = header.name.to_ascii_lowercase().into_bytes();
+                  (name, header.value.to_vec())
+                })
+                .collect();
+              self.buf.advance(index);
+              self.state = State::Body;
+              return Ok(Some(MultipartEvent::Part(headers)));
+            }
+            Status::Partial if self.buf.len() > MAX_PART_HEADERS_SIZE => {
+              return Err(http_error("multipart part headers too large"));
+            }
+            Status::Partial => return Ok(None),
+          }
+        }
+        State::Body => match self.find_delimiter() {
+          Some(0) => {
+            self.buf.advance(self.delimiter.len());
+            self.state = State::AfterDelimiter;
+          }
+          Some(index) => {
+            let data = self.buf.split_to(index).freeze();
+            return Ok(Some(MultipartEvent::Data(data)));
+          }
+          None => {
+            let len = self.undelimited_len();
+            if len == 0 {
+              return Ok(None);
+            }
+            let data = self.buf.split_to(len).freeze();
+            return Ok(Some(MultipartEvent::Data(data)));
+          }
+        },
+        State::Done => return Ok(None),
+      }
+    }
+  }
+
+  fn find_delimiter(&self) -> Option<usize> {
+    TwoWaySearcher::new(&self.delimiter).search_in(&self.buf)
+  }
+
+  /// Returns how many buffered bytes cannot be part of a delimiter that is
+  /// only partially received.
+  fn undelimited_len(&self) -> usize {
+    self.buf.len().saturating_sub(self.delimiter.len() - 1)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const BODY: &[u8] = b"preamble\r\n--boundary\r\n\
+    Content-Disposition: form-data; name=\"a\"\r\n\r\n\
+    hello\r\n--boundary \r\n\
+    Content-Disposition: form-data; name=\"b\"; filename=\"b.txt\"\r\n\
+    Content-Type: text/plain\r\n\r\n\
+    line 1\r\nline 2\r\n--boundary--\r\nepilogue";
+
+  /// Parses `body` fed in chunks of `chunk_size` bytes, merging the data of
+  /// each part.
+  fn parse(body: &[u8], chunk_size: usize) -> Vec<MultipartEvent> {
+    let mut parser = MultipartParser::new("boundary");
+    let mut events = vec![];
+    for chunk in body.chunks(chunk_size) {
+      parser.feed(chunk);
+      while let Some(event) = parser.next_event().unwrap() {
+        match (events.last_mut(), event) {
+          (Some(MultipartEvent::Data(data)), MultipartEvent::Data(more)) => {
+            *data = [&data[..], &more[..]].concat().into();
+          }
+          (_, event) => events.push(event),
+        }
+      }
+    }
+    parser.finish().unwrap();
+    events
+  }
+
+  #[test]
+  fn parts() {
+    let expected = vec![
+      MultipartEvent::Part(vec![(
+        b"content-disposition".to_vec(),
+        b"form-data; name=\"a\"".to_vec(),
+      )]),
+      MultipartEvent::Data(Bytes::from("hello")),
+      MultipartEvent::Part(vec![
+        (
+          b"content-disposition".to_vec(),
+          b"form-data; name=\"b\"; filename=\"b.txt\"".to_vec(),
+        ),
+        (b"content-type".to_vec(), b"text/plain".to_vec()),
+      ]),
+      MultipartEvent::Data(Bytes::from("line 1\r\nline 2")),
+      MultipartEvent::End,
+    ];
+    for chunk_size in [1, 2, 7, 13, BODY.len()] {
+      assert_eq!(parse(BODY, chunk_size), expected, "{chunk_size}");
+    }
+  }
+
+  #[test]
+  fn empty_part() {
+    let events = parse(b"--boundary\r\n\r\n\r\n--boundary--", 3);
+    assert_eq!(
+      events,
+      vec![MultipartEvent::Part(vec![]), MultipartEvent::End]
+    );
+  }
+
+  #[test]
+  fn truncated() {
+    let mut parser = MultipartParser::new("boundary");
+    parser.feed(b"--boundary\r\n\r\nhello\r\n--bound");
+    while parser.next_event().unwrap().is_some() {}
+    assert!(parser.finish().is_err());
+  }
+
+  #[test]
+  fn invalid_delimiter() {
+    let mut parser = MultipartParser::new("boundary");
+    parser.feed(b"--boundary_x\r\n\r\n");
+    assert!(parser.next_event().is_err());
+  }
+}
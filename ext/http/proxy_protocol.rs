@@ -0,0 +1,171 @@
+// Copyright 2018-2024 the Deno authors. All rights reserved. MIT license.
+
+//! Reads the PROXY protocol header that load balancers put in front of a
+//! forwarded connection: <https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt>
+
+use std::io;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::net::SocketAddr;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncReadExt;
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V1_MAX_LEN: usize = 107;
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Reads and removes a PROXY protocol v1 or v2 header from the front of
+/// `io`, returning the client address it carries. `None` means the proxy
+/// did not forward an address (`UNKNOWN` or `LOCAL`), eg: for health checks.
+///
+/// The header is read exactly, without consuming any bytes that follow it,
+/// so `io` can be handed to hyper afterwards. A missing or malformed header
+/// is an `InvalidData` error.
+pub async fn read_proxy_header<S: AsyncRead + Unpin>(
+  io: &mut S,
+) -> io::Result<Option<SocketAddr>> {
+  // Both versions are longer than the v2 signature, so reading that many
+  // bytes never blocks on a valid header.
+  let mut start = [0; 12];
+  io.read_exact(&mut start).await?;
+  if &start == V2_SIGNATURE {
+    read_v2(io).await
+  } else if start.starts_with(V1_PREFIX) {
+    read_v1(io, &start).await
+  } else {
+    Err(invalid_header())
+  }
+}
+
+async fn read_v1<S: AsyncRead + Unpin>(
+  io: &mut S,
+  start: &[u8],
+) -> io::Result<Option<SocketAddr>> {
+  let mut line = start.to_vec();
+  while !line.ends_with(b"\r\n") {
+    if line.len() == V1_MAX_LEN {
+      return Err(invalid_header());
+    }
+    line.push(io.read_u8().await?);
+  }
+
+  let line = std::str::from_utf8(&line[V1_PREFIX.len()..line.len() - 2])
+    .map_err(|_| invalid_header())?;
+  let mut fields = line.split(' ');
+  match fields.next() {
+    Some("UNKNOWN") => return Ok(None),
+    Some("TCP4" | "TCP6") => {}
+    _ => return Err(invalid_header()),
+  }
+  let src_ip: IpAddr = parse_field(fields.next())?;
+  let _dst_ip: IpAddr = parse_field(fields.next())?;
+  let src_port: u16 = parse_field(fields.next())?;
+  let _dst_port: u16 = parse_field(fields.next())?;
+  if fields.next().is_some() {
+    return Err(invalid_header());
+  }
+  Ok(Some(SocketAddr::new(src_ip, src_port)))
+}
+
+async fn read_v2<S: AsyncRead + Unpin>(
+  io: &mut S,
+) -> io::Result<Option<SocketAddr>> {
+  let version_command = io.read_u8().await?;
+  let family = io.read_u8().await?;
+  let len = io.read_u16().await? as usize;
+  let mut payload = vec![0; len];
+  io.read_exact(&mut payload).await?;
+
+  match version_command {
+    0x20 => return Ok(None), // LOCAL
+    0x21 => {}               // PROXY
+    _ => return Err(invalid_header()),
+  }
+  // Only the source address is used; TLVs after the addresses are ignored.
+  match family {
+    // TCP over IPv4
+    0x11 if len >= 12 => {
+      let ip: [u8; 4] = payload[..4].try_into().unwrap();
+      let port = u16::from_be_bytes([payload[8], payload[9]]);
+      Ok(Some(SocketAddr::new(Ipv4Addr::from(ip).into(), port)))
+    }
+    // TCP over IPv6
+    0x21 if len >= 36 => {
+      let ip: [u8; 16] = payload[..16].try_into().unwrap();
+      let port = u16::from_be_bytes([payload[32], payload[33]]);
+      Ok(Some(SocketAddr::new(Ipv6Addr::from(ip).into(), port)))
+    }
+    // UNSPEC, or a transport without an IP address.
+    0x00 | 0x31 | 0x32 => Ok(None),
+    _ => Err(invalid_header()),
+  }
+}
+
+fn parse_field<T: std::str::FromStr>(field: Option<&str>) -> io::Result<T> {
+  field
+    .and_then(|field| field.parse().ok())
+    .ok_or_else(invalid_header)
+}
+
+fn invalid_header() -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, "invalid PROXY protocol header")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  async fn read(mut input: &[u8]) -> (io::Result<Option<SocketAddr>>, &[u8]) {
+    let result = read_proxy_header(&mut input).await;
+    (result, input)
+  }
+
+  #[tokio::test]
+  async fn v1() {
+    let input = b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET /";
+    let (result, rest) = read(input).await;
+    assert_eq!(result.unwrap(), Some("192.0.2.1:56324".parse().unwrap()));
+    assert_eq!(rest, b"GET /");
+
+    let input = b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 443\r\n";
+    let (result, _) = read(input).await;
+    assert_eq!(
+      result.unwrap(),
+      Some("[2001:db8::1]:56324".parse().unwrap())
+    );
+
+    let (result, rest) = read(b"PROXY UNKNOWN\r\nGET /").await;
+    assert_eq!(result.unwrap(), None);
+    assert_eq!(rest, b"GET /");
+  }
+
+  #[tokio::test]
+  async fn v2() {
+    let mut input = V2_SIGNATURE.to_vec();
+    input.extend([0x21, 0x11, 0, 12]);
+    input.extend([192, 0, 2, 1, 198, 51, 100, 1]);
+    input.extend(56324u16.to_be_bytes());
+    input.extend(443u16.to_be_bytes());
+    input.extend(b"GET /");
+    let (result, rest) = read(&input).await;
+    assert_eq!(result.unwrap(), Some("192.0.2.1:56324".parse().unwrap()));
+    assert_eq!(rest, b"GET /");
+
+    let mut input = V2_SIGNATURE.to_vec();
+    input.extend([0x20, 0x00, 0, 0]);
+    let (result, _) = read(&input).await;
+    assert_eq!(result.unwrap(), None);
+  }
+
+  #[tokio::test]
+  async fn invalid() {
+    let (result, _) = read(b"GET / HTTP/1.1\r\n\r\n").await;
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    let (result, _) = read(b"PROXY TCP4 192.0.2.1\r\n").await;
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    let long = format!("PROXY TCP4 {}\r\n", "1".repeat(V1_MAX_LEN));
+    let (result, _) = read(long.as_bytes()).await;
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+  }
+}
@@ -613,6 +613,7 @@ function createWebSocketBranded() {
 
 export {
   _eventLoop,
+  _extensions,
   _idleTimeoutDuration,
   _idleTimeoutTimeout,
   _protocol,
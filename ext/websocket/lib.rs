@@ -55,11 +55,13 @@ use tokio::io::WriteHalf;
 use tokio::net::TcpStream;
 
 use fastwebsockets::CloseCode;
-use fastwebsockets::FragmentCollectorRead;
 use fastwebsockets::Frame;
 use fastwebsockets::OpCode;
+use fastwebsockets::Payload;
 use fastwebsockets::Role;
 use fastwebsockets::WebSocket;
+use fastwebsockets::WebSocketError;
+use fastwebsockets::WebSocketRead;
 use fastwebsockets::WebSocketWrite;
 
 mod stream;
@@ -465,7 +467,8 @@ pub struct ServerWebSocket {
   closed: Cell<bool>,
   buffer: Cell<Option<Vec<u8>>>,
   string: Cell<Option<String>>,
-  ws_read: AsyncRefCell<FragmentCollectorRead<ReadHalf<WebSocketStream>>>,
+  max_message_size: usize,
+  ws_read: AsyncRefCell<MessageReader>,
   ws_write: AsyncRefCell<WebSocketWrite<WriteHalf<WebSocketStream>>>,
 }
 
@@ -479,7 +482,11 @@ impl ServerWebSocket {
       closed: Cell::new(false),
       buffer: Cell::new(None),
       string: Cell::new(None),
-      ws_read: AsyncRefCell::new(FragmentCollectorRead::new(ws_read)),
+      max_message_size: usize::MAX,
+      ws_read: AsyncRefCell::new(MessageReader {
+        ws: ws_read,
+        fragments: None,
+      }),
       ws_write: AsyncRefCell::new(ws_write),
     }
   }
@@ -518,16 +525,91 @@ impl ServerWebSocket {
   }
 }
 
+/// Reassembles fragmented messages, like `FragmentCollectorRead`, but fails as
+/// soon as the fragments of a message grow past the size limit.
+struct MessageReader {
+  ws: WebSocketRead<ReadHalf<WebSocketStream>>,
+  fragments: Option<(OpCode, Vec<u8>)>,
+}
+
+impl MessageReader {
+  async fn read_message<'f, R, E>(
+    &mut self,
+    max_message_size: usize,
+    send_fn: &mut impl FnMut(Frame<'f>) -> R,
+  ) -> Result<Frame<'static>, AnyError>
+  where
+    E: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
+    R: Future<Output = Result<(), E>>,
+  {
+    loop {
+      let frame = self.ws.read_frame(send_fn).await?;
+      match frame.opcode {
+        OpCode::Text | OpCode::Binary => {
+          if self.fragments.is_some() {
+            return Err(WebSocketError::InvalidFragment.into());
+          }
+          if frame.payload.len() > max_message_size {
+            return Err(type_error("Message too big"));
+          }
+          let payload = Vec::from(frame.payload);
+          if frame.fin {
+            return Ok(Frame::new(
+              true,
+              frame.opcode,
+              None,
+              Payload::Owned(payload),
+            ));
+          }
+          self.fragments = Some((frame.opcode, payload));
+        }
+        OpCode::Continuation => {
+          let Some((_, buf)) = self.fragments.as_mut() else {
+            return Err(WebSocketError::InvalidContinuationFrame.into());
+          };
+          if buf.len() + frame.payload.len() > max_message_size {
+            self.fragments = None;
+            return Err(type_error("Message too big"));
+          }
+          buf.extend_from_slice(&frame.payload);
+          if frame.fin {
+            let (opcode, buf) = self.fragments.take().unwrap();
+            if opcode == OpCode::Text && std::str::from_utf8(&buf).is_err() {
+              return Err(WebSocketError::InvalidUTF8.into());
+            }
+            return Ok(Frame::new(true, opcode, None, Payload::Owned(buf)));
+          }
+        }
+        _ => {
+          return Ok(Frame::new(
+            frame.fin,
+            frame.opcode,
+            None,
+            Payload::Owned(frame.payload.into()),
+          ))
+        }
+      }
+    }
+  }
+}
+
 impl Resource for ServerWebSocket {
   fn name(&self) -> Cow<str> {
     "serverWebSocket".into()
   }
 }
 
+/// Creates a server WebSocket resource over an upgraded `transport`.
+/// `max_frame_size` limits the payload size of each frame received from the
+/// client; it defaults to 64 MiB. `max_message_size` limits the size of a
+/// message across all of its fragments, and is unlimited by default. A message
+/// fails on the first fragment that takes it past the limit.
 pub fn ws_create_server_stream(
   state: &mut OpState,
   transport: NetworkStream,
   read_buf: Bytes,
+  max_message_size: Option<usize>,
+  max_frame_size: Option<usize>,
 ) -> Result<ResourceId, AnyError> {
   let mut ws = WebSocket::after_handshake(
     WebSocketStream::new(
@@ -539,8 +621,15 @@ pub fn ws_create_server_stream(
   ws.set_writev(*USE_WRITEV);
   ws.set_auto_close(true);
   ws.set_auto_pong(true);
+  if let Some(max_frame_size) = max_frame_size {
+    ws.set_max_message_size(max_frame_size);
+  }
 
-  let rid = state.resource_table.add(ServerWebSocket::new(ws));
+  let mut resource = ServerWebSocket::new(ws);
+  if let Some(max_message_size) = max_message_size {
+    resource.max_message_size = max_message_size;
+  }
+  let rid = state.resource_table.add(resource);
   Ok(rid)
 }
 
@@ -758,7 +847,9 @@ pub async fn op_ws_next_event(
     async move { writer.borrow_mut().await.write_frame(frame).await }
   };
   loop {
-    let res = ws.read_frame(&mut sender).await;
+    let res = ws
+      .read_message(resource.max_message_size, &mut sender)
+      .await;
     let val = match res {
       Ok(val) => val,
       Err(err) => {
@@ -774,7 +865,7 @@ pub async fn op_ws_next_event(
     };
 
     break match val.opcode {
-      OpCode::Text => match String::from_utf8(val.payload.to_vec()) {
+      OpCode::Text => match String::from_utf8(val.payload.into()) {
         Ok(s) => {
           resource.string.set(Some(s));
           MessageKind::Text as u16
@@ -785,7 +876,7 @@ pub async fn op_ws_next_event(
         }
       },
       OpCode::Binary => {
-        resource.buffer.set(Some(val.payload.to_vec()));
+        resource.buffer.set(Some(val.payload.into()));
         MessageKind::Binary as u16
       }
       OpCode::Close => {
@@ -116,6 +116,13 @@ class Http extends Error {
   }
 }
 
+class RequestBodyTooLarge extends Http {
+  constructor(msg) {
+    super(msg);
+    this.name = "RequestBodyTooLarge";
+  }
+}
+
 class Busy extends Error {
   constructor(msg) {
     super(msg);
@@ -177,6 +184,7 @@ const errors = {
   UnexpectedEof,
   BadResource,
   Http,
+  RequestBodyTooLarge,
   Busy,
   NotSupported,
   FilesystemLoop,
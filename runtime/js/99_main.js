@@ -414,6 +414,7 @@ core.registerErrorClass("WouldBlock", errors.WouldBlock);
 core.registerErrorClass("WriteZero", errors.WriteZero);
 core.registerErrorClass("UnexpectedEof", errors.UnexpectedEof);
 core.registerErrorClass("Http", errors.Http);
+core.registerErrorClass("RequestBodyTooLarge", errors.RequestBodyTooLarge);
 core.registerErrorClass("Busy", errors.Busy);
 core.registerErrorClass("NotSupported", errors.NotSupported);
 core.registerErrorClass("FilesystemLoop", errors.FilesystemLoop);
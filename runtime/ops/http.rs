@@ -10,6 +10,7 @@ use deno_core::OpState;
 use deno_core::ResourceId;
 use deno_core::ToJsBuffer;
 use deno_http::http_create_conn_resource;
+use deno_http::HttpConnOptions;
 use deno_net::io::TcpStreamResource;
 use deno_net::ops_tls::TlsStreamResource;
 use serde::Serialize;
@@ -18,12 +19,15 @@ pub const UNSTABLE_FEATURE_NAME: &str = "http";
 
 deno_core::extension!(deno_http_runtime, ops = [op_http_start],);
 
-#[op2(fast)]
+#[op2]
 #[smi]
 fn op_http_start(
   state: &mut OpState,
   #[smi] tcp_stream_rid: ResourceId,
+  #[serde] options: Option<HttpConnOptions>,
 ) -> Result<ResourceId, AnyError> {
+  let options = options.unwrap_or_default();
+
   if let Ok(resource_rc) = state
     .resource_table
     .take::<TcpStreamResource>(tcp_stream_rid)
@@ -36,7 +40,7 @@ fn op_http_start(
     let (read_half, write_half) = resource.into_inner();
     let tcp_stream = read_half.reunite(write_half)?;
     let addr = tcp_stream.local_addr()?;
-    return http_create_conn_resource(state, tcp_stream, addr, "http");
+    return http_create_conn_resource(state, tcp_stream, addr, "http", options);
   }
 
   if let Ok(resource_rc) = state
@@ -51,7 +55,9 @@ fn op_http_start(
     let (read_half, write_half) = resource.into_inner();
     let tls_stream = read_half.unsplit(write_half);
     let addr = tls_stream.local_addr()?;
-    return http_create_conn_resource(state, tls_stream, addr, "https");
+    return http_create_conn_resource(
+      state, tls_stream, addr, "https", options,
+    );
   }
 
   #[cfg(unix)]
@@ -69,7 +75,13 @@ fn op_http_start(
     let (read_half, write_half) = resource.into_inner();
     let unix_stream = read_half.reunite(write_half)?;
     let addr = unix_stream.local_addr()?;
-    return http_create_conn_resource(state, unix_stream, addr, "http+unix");
+    return http_create_conn_resource(
+      state,
+      unix_stream,
+      addr,
+      "http+unix",
+      options,
+    );
   }
 
   Err(bad_resource_id())
@@ -20,6 +20,9 @@ Deno.test("Errors work", () => {
   assert(new Deno.errors.UnexpectedEof("msg") instanceof Error);
   assert(new Deno.errors.BadResource("msg") instanceof Error);
   assert(new Deno.errors.Http("msg") instanceof Error);
+  assert(
+    new Deno.errors.RequestBodyTooLarge("msg") instanceof Deno.errors.Http,
+  );
   assert(new Deno.errors.Busy("msg") instanceof Error);
   assert(new Deno.errors.NotSupported("msg") instanceof Error);
 });
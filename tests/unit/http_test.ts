@@ -1,5 +1,7 @@
 // Copyright 2018-2024 the Deno authors. All rights reserved. MIT license.
 import { Buffer, BufReader, BufWriter } from "@std/io/mod.ts";
+import { readAll } from "@std/io/read_all.ts";
+import { concat } from "@std/bytes/concat.ts";
 import { TextProtoReader } from "../testdata/run/textproto.ts";
 import {
   assert,
@@ -17,8 +19,36 @@ const listenPort2 = 4508;
 
 const {
   buildCaseInsensitiveCommaValueFinder,
+  core,
   // @ts-expect-error TypeScript (as of 3.7) does not support indexing namespaces by symbol
 } = Deno[Deno.internal];
+const {
+  op_http_accept,
+  op_http_active_conns,
+  op_http_body_consumed,
+  op_http_conn_count,
+  op_http_conn_stats,
+  op_http_get_peer_certificates,
+  op_http_graceful_shutdown,
+  op_http_headers,
+  op_http_multipart,
+  op_http_multipart_next,
+  op_http_read_all,
+  op_http_read_chunk,
+  op_http_read_frame,
+  op_http_read_to_file,
+  op_http_request_on_cancel,
+  op_http_request_trailers,
+  op_http_reset,
+  op_http_response_abort,
+  op_http_response_http10,
+  op_http_set_tcp_keepalive,
+  op_http_tcp_keepalive,
+  op_http_upgrade_connect,
+  op_http_write,
+  op_http_write_full,
+  op_http_write_headers,
+} = core.ops;
 
 async function writeRequestAndReadResponse(conn: Deno.Conn): Promise<string> {
   const encoder = new TextEncoder();
@@ -803,6 +833,87 @@ Deno.test({ permissions: { net: true } }, async function httpServerWebSocket() {
   await promise;
 });
 
+Deno.test(
+  { permissions: { net: true } },
+  async function httpServerWebSocketMaxMessageSize() {
+    const promise = (async () => {
+      const listener = Deno.listen({ port: listenPort });
+      const conn = await listener.accept();
+      listener.close();
+      const httpConn = Deno.serveHttp(conn);
+      const reqEvent = await httpConn.nextRequest();
+      assert(reqEvent);
+      const { request, respondWith } = reqEvent;
+      const { response, socket } = Deno.upgradeWebSocket(request, {
+        maxMessageSize: 8,
+        maxFrameSize: 16,
+      });
+      socket.onmessage = () => fail();
+      const error = new Promise<ErrorEvent>((resolve) => {
+        socket.onerror = (e) => resolve(e as ErrorEvent);
+      });
+      await respondWith(response);
+      assertEquals((await error).message, "Message too big");
+    })();
+
+    const def = Promise.withResolvers<void>();
+    const ws = new WebSocket(`ws://localhost:${listenPort}`);
+    ws.onclose = () => def.resolve();
+    ws.onopen = () => ws.send("0123456789");
+    await def.promise;
+    await promise;
+  },
+);
+
+Deno.test(
+  { permissions: { net: true } },
+  async function httpServerWebSocketMaxMessageSizeFragmented() {
+    const { client, httpConn } = await serveHttpRaw();
+    const promise = (async () => {
+      const reqEvent = await httpConn.nextRequest();
+      assert(reqEvent);
+      const { request, respondWith } = reqEvent;
+      const { response, socket } = Deno.upgradeWebSocket(request, {
+        maxMessageSize: 8,
+      });
+      socket.onmessage = () => fail();
+      const error = new Promise<ErrorEvent>((resolve) => {
+        socket.onerror = (e) => resolve(e as ErrorEvent);
+      });
+      await respondWith(response);
+      assertEquals((await error).message, "Message too big");
+    })();
+
+    await client.write(
+      new TextEncoder().encode(
+        "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade\r\n" +
+          "Upgrade: websocket\r\nSec-WebSocket-Version: 13\r\n" +
+          "Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+      ),
+    );
+    const buf = new Uint8Array(1024);
+    assert(await client.read(buf));
+    // Three masked fragments of 4, 4 and 1 bytes, none of them final, so the
+    // limit applies before the message is complete.
+    const fragment = (op: number, payload: string) =>
+      new Uint8Array([
+        op,
+        0x80 | payload.length,
+        0,
+        0,
+        0,
+        0,
+        ...new TextEncoder().encode(payload),
+      ]);
+    await client.write(fragment(0x01, "0123"));
+    await client.write(fragment(0x00, "4567"));
+    await client.write(fragment(0x00, "8"));
+    await promise;
+    client.close();
+    httpConn.close();
+  },
+);
+
 Deno.test(function httpUpgradeWebSocket() {
   const request = new Request("https://deno.land/", {
     headers: {
@@ -2668,6 +2779,41 @@ Deno.test(
   },
 );
 
+Deno.test(
+  { permissions: { net: true } },
+  async function httpServerHeadResponseHasNoBody() {
+    const promise = (async () => {
+      const listener = Deno.listen({ port: listenPort });
+      const conn = await listener.accept();
+      listener.close();
+      const httpConn = Deno.serveHttp(conn);
+      const event = await httpConn.nextRequest();
+      assert(event);
+      assertEquals(event.request.method, "HEAD");
+      await event.respondWith(new Response("Hello World"));
+      httpConn.close();
+    })();
+
+    const conn = await Deno.connect({ port: listenPort });
+    const request = new TextEncoder().encode(
+      `HEAD / HTTP/1.1\r\nHost: 127.0.0.1:${listenPort}\r\n\r\n`,
+    );
+    await conn.write(request);
+    await promise;
+
+    const dest = new Buffer();
+    const buf = new Uint8Array(1024);
+    let nread;
+    while ((nread = await conn.read(buf)) !== null) {
+      await dest.write(buf.subarray(0, nread));
+    }
+    conn.close();
+    const respText = new TextDecoder().decode(dest.bytes());
+    assert(respText.includes("content-length: 11\r\n"));
+    assert(respText.endsWith("\r\n\r\n"));
+  },
+);
+
 Deno.test("proxy with fetch", async () => {
   const listener = Deno.listen({ port: listenPort });
   const deferred = Promise.withResolvers<void>();
@@ -2723,6 +2869,858 @@ Deno.test("proxy with fetch", async () => {
   httpConn!.close();
 });
 
+Deno.test(
+  { permissions: { net: true } },
+  async function httpReadAllDeclaredLengthNotSent() {
+    const { client, httpConn } = await serveHttpRaw();
+    await client.write(
+      new TextEncoder().encode(
+        "POST / HTTP/1.1\r\nContent-Length: 1073741824\r\n\r\nhello",
+      ),
+    );
+    const [readRid] = await op_http_accept(httpConn.rid);
+    // Only what arrived is buffered, not the declared gigabyte.
+    const read = op_http_read_all(readRid, 2 ** 31);
+    await client.closeWrite();
+    await assertRejects(() => read);
+    client.close();
+    httpConn.close();
+  },
+);
+
+Deno.test(
+  { permissions: { net: true } },
+  async function httpWriteFullTrailersOverHttp1() {
+    const { client, httpConn } = await serveHttpRaw();
+    await client.write(
+      new TextEncoder().encode("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n"),
+    );
+    const [, writeRid] = await op_http_accept(httpConn.rid);
+    const body = new TextEncoder().encode("hello");
+    await assertRejects(
+      () => op_http_write_full(writeRid, 200, [], body, [["foo", "bar"]]),
+      Deno.errors.Http,
+      "trailers can only be sent over HTTP/2",
+    );
+    // Nothing was written, so the response can still be sent without them.
+    await op_http_write_full(writeRid, 200, [], body, []);
+    const buf = new Uint8Array(1024);
+    const n = await client.read(buf);
+    const response = new TextDecoder().decode(buf.subarray(0, n!));
+    assert(response.startsWith("HTTP/1.1 200 OK\r\n"));
+    assert(response.endsWith("\r\n\r\nhello"));
+    client.close();
+    httpConn.close();
+  },
+);
+
+Deno.test(
+  { permissions: { net: true, read: true } },
+  async function httpPeerCertificatesWithoutClientCert() {
+    const { client, httpConn } = await serveHttpRaw();
+    assertEquals(await op_http_get_peer_certificates(httpConn.rid), null);
+    client.close();
+    httpConn.close();
+
+    const listener = Deno.listenTls({
+      hostname: "localhost",
+      port: listenPort,
+      cert: await Deno.readTextFile("tests/testdata/tls/localhost.crt"),
+      key: await Deno.readTextFile("tests/testdata/tls/localhost.key"),
+    });
+    const [tlsClient, conn] = await Promise.all([
+      Deno.connectTls({
+        hostname: "localhost",
+        port: listenPort,
+        caCerts: [await Deno.readTextFile("tests/testdata/tls/RootCA.pem")],
+      }),
+      listener.accept(),
+    ]);
+    listener.close();
+    const tlsHttpConn = Deno.serveHttp(conn);
+    // Deno.listenTls does not ask clients for a certificate.
+    assertEquals(await op_http_get_peer_certificates(tlsHttpConn.rid), null);
+    tlsClient.close();
+    tlsHttpConn.close();
+  },
+);
+
+Deno.test(
+  { permissions: { net: true } },
+  async function httpCloseStreamsOnConnError() {
+    const { client, httpConn } = await serveHttpRaw({
+      http2PriorKnowledge: true,
+    });
+    await client.write(h2ClientPreface());
+    // `:method: POST`, `:scheme: http` and `:path: /` from the HPACK static
+    // table.
+    await client.write(h2Frame(0x1, 0x4, 1, [0x83, 0x86, 0x84]));
+    const [readRid, writeRid] = await op_http_accept(httpConn.rid);
+
+    // A DATA frame on stream 0 is a connection error, after which the streams
+    // of the pending request are closed.
+    await client.write(h2Frame(0x0, 0, 0));
+    await assertRejects(() => op_http_accept(httpConn.rid));
+    assertThrows(() => op_http_body_consumed(readRid), Deno.errors.BadResource);
+    assertThrows(
+      () => op_http_response_http10(writeRid),
+      Deno.errors.BadResource,
+    );
+    client.close();
+    httpConn.close();
+  },
+);
+
+Deno.test(
+  { permissions: { net: true } },
+  async function httpConnStats() {
+    const { client, httpConn } = await serveHttpRaw();
+    await client.write(
+      new TextEncoder().encode(
+        "POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello",
+      ),
+    );
+    const [readRid, writeRid] = await op_http_accept(httpConn.rid);
+    const body = await op_http_read_all(readRid, 16);
+    assertEquals(new TextDecoder().decode(body), "hello");
+    await op_http_write_full(
+      writeRid,
+      200,
+      [],
+      new TextEncoder().encode("hi!"),
+      [],
+    );
+    assertEquals(op_http_conn_stats(httpConn.rid), {
+      requests: 1,
+      bytesRead: 5,
+      bytesWritten: 3,
+    });
+    client.close();
+    httpConn.close();
+  },
+);
+
+Deno.test(
+  { permissions: { net: true } },
+  async function httpBrotliOptionsOutOfRange() {
+    const invalid = [
+      [{ brotliQuality: 12 }, "brotliQuality must be between 0 and 11"],
+      [{ brotliWindow: 9 }, "brotliWindow must be between 10 and 24"],
+      [{ brotliWindow: 25 }, "brotliWindow must be between 10 and 24"],
+    ] as const;
+    const listener = Deno.listen({ hostname: "127.0.0.1", port: listenPort });
+    for (const [options, message] of invalid) {
+      const [client, conn] = await Promise.all([
+        Deno.connect({ hostname: "127.0.0.1", port: listenPort }),
+        listener.accept(),
+      ]);
+      // The connection is closed along with the failed server.
+      assertThrows(() => Deno.serveHttp(conn, options), TypeError, message);
+      client.close();
+    }
+    listener.close();
+  },
+);
+
+Deno.test(
+  { permissions: { net: true } },
+  async function httpTcpKeepaliveOnLiveConnection() {
+    const { client, httpConn } = await serveHttpRaw();
+    const keepalive = { idle: 30_000, interval: 5_000, count: 3 };
+    assert(op_http_set_tcp_keepalive(httpConn.rid, keepalive));
+    assertEquals(op_http_tcp_keepalive(httpConn.rid), true);
+    assert(op_http_set_tcp_keepalive(httpConn.rid, null));
+    assertEquals(op_http_tcp_keepalive(httpConn.rid), false);
+
+    // The socket is forgotten once the connection is closed.
+    client.close();
+    assertEquals(await op_http_accept(httpConn.rid), null);
+    assertEquals(op_http_tcp_keepalive(httpConn.rid), null);
+    assertEquals(op_http_set_tcp_keepalive(httpConn.rid, keepalive), false);
+    httpConn.close();
+  },
+);
+
+Deno.test(
+  { permissions: { net: true } },
+  async function httpActiveConns() {
+    const { client, httpConn } = await serveHttpRaw();
+    const { port } = client.localAddr as Deno.NetAddr;
+    assertEquals(op_http_active_conns(), [
+      { rid: httpConn.rid, peerAddr: ["127.0.0.1", port], requests: 0 },
+    ]);
+    client.close();
+    httpConn.close();
+    assertEquals(op_http_active_conns(), []);
+  },
+);
+
+Deno.test(
+  { permissions: { net: true } },
+  async function httpConnCount() {
+    const count = op_http_conn_count();
+    const { client, httpConn } = await serveHttpRaw();
+    assertEquals(op_http_conn_count(), count + 1);
+
+    // The connection stops counting when it closes, before its resource does.
+    client.close();
+    assertEquals(await op_http_accept(httpConn.rid), null);
+    assertEquals(op_http_conn_count(), count);
+    httpConn.close();
+  },
+);
+
+Deno.test(
+  { permissions: { net: true } },
+  async function httpHttp2PriorKnowledge() {
+    const { client, httpConn } = await serveHttpRaw({
+      http2PriorKnowledge: true,
+    });
+    const frames = h2Frames(client);
+    await client.write(h2ClientPreface());
+    // `:method: GET`, `:scheme: http` and `:path: /`.
+    await client.write(h2Frame(0x1, 0x5, 1, [0x82, 0x86, 0x84]));
+
+    const [, writeRid, method, , version] = await op_http_accept(httpConn.rid);
+    assertEquals(method, "GET");
+    assertEquals(version, "HTTP/2.0");
+    await op_http_write_full(writeRid, 200, [], new Uint8Array(), []);
+    // `:status: 200` from the HPACK static table.
+    const headers = await nextH2Frame(frames, 0x1, 1);
+    assertEquals(headers.payload[0], 0x88);
+    client.close();
+    httpConn.close();
+  },
+);
+
+Deno.test(
+  { permissions: { net: true } },
+  async function httpHttp2Settings() {
+    const { client, httpConn } = await serveHttpRaw({
+      http2PriorKnowledge: true,
+      http2MaxConcurrentStreams: 5,
+    });
+    const accept = op_http_accept(httpConn.rid);
+    const frames = h2Frames(client);
+    await client.write(h2ClientPreface());
+
+    const { payload } = await nextH2Frame(frames, 0x4, 0);
+    const view = new DataView(payload.buffer);
+    const settings = new Map<number, number>();
+    for (let i = 0; i < payload.length; i += 6) {
+      settings.set(view.getUint16(i), view.getUint32(i + 2));
+    }
+    // SETTINGS_MAX_CONCURRENT_STREAMS.
+    assertEquals(settings.get(0x3), 5);
+    client.close();
+    assertEquals(await accept, null);
+    httpConn.close();
+  },
+);
+
+Deno.test(
+  { permissions: { net: true } },
+  async function httpMaxHeaders() {
+    const { client, httpConn } = await serveHttpRaw({ maxHeaders: 3 });
+    await client.write(
+      new TextEncoder().encode(
+        "GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\nConnection: close\r\n\r\n",
+      ),
+    );
+    const [accepted, response] = await Promise.all([
+      op_http_accept(httpConn.rid),
+      readAll(client),
+    ]);
+    assertEquals(accepted, null);
+    assert(new TextDecoder().decode(response).startsWith("HTTP/1.1 431 "));
+    client.close();
+    httpConn.close();
+  },
+);
+
+Deno.test(
+  { permissions: { net: true } },
+  async function httpStrictFraming() {
+    // CL.TE smuggling attempts: a proxy using the length would forward the
+    // trailing bytes as the start of another request.
+    const payloads = [
+      "POST / HTTP/1.1\r\nContent-Length: 6\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\nG",
+      "POST / HTTP/1.1\r\nContent-Length: 4\r\nTransfer-Encoding: chunked\r\n\r\n5c\r\nGPOST / HTTP/1.1\r\n\r\n0\r\n\r\n",
+    ];
+    for (const payload of payloads) {
+      const { client, httpConn } = await serveHttpRaw({ strictFraming: true });
+      await client.write(new TextEncoder().encode(payload));
+      const accepted = op_http_accept(httpConn.rid).catch(() => null);
+      const response = await readResponseUntil(client, "\r\n");
+      assert(response.startsWith("HTTP/1.1 400 "), response);
+      assertEquals(await accepted, null);
+      client.close();
+      httpConn.close();
+    }
+  },
+);
+
+Deno.test(
+  { permissions: { net: true, read: true, write: true } },
+  async function httpReadToFile() {
+    for (const [maxSize, expected] of [[11, 11], [10, null]]) {
+      const { client, httpConn } = await serveHttpRaw();
+      await client.write(
+        new TextEncoder().encode(
+          "POST / HTTP/1.1\r\nContent-Length: 11\r\n\r\nhello world",
+        ),
+      );
+      const [readRid] = await op_http_accept(httpConn.rid);
+      const path = await Deno.makeTempFile();
+      using file = await Deno.open(path, { write: true });
+      // deno-lint-ignore no-deprecated-deno-api
+      const read = op_http_read_to_file(readRid, file.rid, maxSize);
+      if (expected === null) {
+        await assertRejects(() => read, Deno.errors.RequestBodyTooLarge);
+      } else {
+        assertEquals(await read, expected);
+        assertEquals(await Deno.readTextFile(path), "hello world");
+      }
+      await Deno.remove(path);
+      client.close();
+      httpConn.close();
+    }
+  },
+);
+
+Deno.test(
+  { permissions: { net: true } },
+  async function httpUpgradeAfterBodyRead() {
+    const { client, httpConn } = await serveHttpRaw();
+    await client.write(
+      new TextEncoder().encode(
+        "GET / HTTP/1.1\r\nConnection: Upgrade\r\nUpgrade: websocket\r\n" +
+          "Content-Length: 2\r\n\r\nab",
+      ),
+    );
+    const [readRid, writeRid] = await op_http_accept(httpConn.rid);
+    const body = await op_http_read_all(readRid, 16);
+    assertEquals(new TextDecoder().decode(body), "ab");
+
+    // The body was read, so a 101 goes out as a 400.
+    const headers = [["connection", "Upgrade"], ["upgrade", "websocket"]];
+    await assertRejects(() =>
+      op_http_write_headers(writeRid, 101, headers, null)
+    );
+    const response = await readResponseUntil(client, "\r\n");
+    assert(response.startsWith("HTTP/1.1 400 Bad Request\r\n"), response);
+    client.close();
+    httpConn.close();
+  },
+);
+
+Deno.test(
+  { permissions: { net: true } },
+  async function httpDecompressGzipRequestBody() {
+    const body = new Uint8Array(
+      await new Response(
+        new Blob(["hello world"]).stream().pipeThrough(
+          new CompressionStream("gzip"),
+        ),
+      ).arrayBuffer(),
+    );
+    const { client, httpConn } = await serveHttpRaw({
+      decompressRequestBody: true,
+    });
+    await client.write(
+      new TextEncoder().encode(
+        "POST / HTTP/1.1\r\nContent-Encoding: gzip\r\n" +
+          `Content-Length: ${body.length}\r\n\r\n`,
+      ),
+    );
+    await client.write(body);
+    const [readRid] = await op_http_accept(httpConn.rid);
+    const headers = new Headers(op_http_headers(readRid));
+    assertEquals(headers.get("content-encoding"), null);
+    assertEquals(headers.get("content-length"), null);
+    const decoded = [];
+    let chunk;
+    while ((chunk = await op_http_read_chunk(readRid, 64)) !== null) {
+      decoded.push(chunk);
+    }
+    assertEquals(new TextDecoder().decode(concat(decoded)), "hello world");
+    client.close();
+    httpConn.close();
+  },
+);
+
+Deno.test(
+  { permissions: { net: true } },
+  async function httpReadChunkLimit() {
+    const { client, httpConn } = await serveHttpRaw();
+    await client.write(
+      new TextEncoder().encode(
+        "POST / HTTP/1.1\r\nContent-Length: 11\r\n\r\nhello world",
+      ),
+    );
+    const [readRid] = await op_http_accept(httpConn.rid);
+    const chunks = [];
+    let chunk;
+    while ((chunk = await op_http_read_chunk(readRid, 4)) !== null) {
+      chunks.push(chunk);
+    }
+    assertEquals(new TextDecoder().decode(concat(chunks)), "hello world");
+    assert(chunks.every((chunk) => chunk.length <= 4));
+    client.close();
+    httpConn.close();
+  },
+);
+
+Deno.test(
+  { permissions: { net: true } },
+  async function httpHealthCheck() {
+    const { client, httpConn } = await serveHttpRaw({
+      healthCheck: { path: "/healthz", status: 203, body: "ok" },
+    });
+    await client.write(
+      new TextEncoder().encode(
+        "GET /healthz?full HTTP/1.1\r\n\r\nGET /other HTTP/1.1\r\n\r\n",
+      ),
+    );
+    const [accepted, response] = await Promise.all([
+      op_http_accept(httpConn.rid),
+      readResponseUntil(client, "ok"),
+    ]);
+    // Only the other request reaches the handler.
+    const [, , , url] = accepted;
+    assert(url.endsWith("/other"), url);
+    assert(response.startsWith("HTTP/1.1 203 "), response);
+    client.close();
+    httpConn.close();
+  },
+);
+
+Deno.test(
+  { permissions: { net: true } },
+  async function httpBodyReadTimeout() {
+    const { client, httpConn } = await serveHttpRaw({ bodyReadTimeout: 50 });
+    await client.write(
+      new TextEncoder().encode(
+        "POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nab",
+      ),
+    );
+    const [readRid] = await op_http_accept(httpConn.rid);
+    const chunk = await op_http_read_chunk(readRid, 16);
+    assertEquals(new TextDecoder().decode(chunk), "ab");
+    await assertRejects(
+      () => op_http_read_chunk(readRid, 16),
+      Deno.errors.TimedOut,
+    );
+    // The body stays unreadable even once more data arrives.
+    await client.write(new TextEncoder().encode("cdefghij"));
+    await assertRejects(() => op_http_read_chunk(readRid, 16));
+    client.close();
+    httpConn.close();
+  },
+);
+
+Deno.test(
+  { permissions: { net: true } },
+  async function httpBodyWriteTimeout() {
+    const { client, httpConn } = await serveHttpRaw({ bodyWriteTimeout: 100 });
+    await client.write(new TextEncoder().encode("GET / HTTP/1.1\r\n\r\n"));
+    const [, writeRid] = await op_http_accept(httpConn.rid);
+    await op_http_write_headers(writeRid, 200, [], null);
+
+    // The client never reads, so the socket buffers eventually fill up.
+    const chunk = new Uint8Array(1024 * 1024);
+    const error = await (async () => {
+      while (true) {
+        try {
+          await op_http_write(writeRid, chunk);
+        } catch (error) {
+          return error;
+        }
+      }
+    })();
+    assert(error instanceof Deno.errors.TimedOut);
+    // The connection is closed rather than left with a half-sent response.
+    await op_http_graceful_shutdown(httpConn.rid);
+    client.close();
+    httpConn.close();
+  },
+);
+
+Deno.test(
+  { permissions: { net: true } },
+  async function httpHttp2RequestTrailers() {
+    const { client, httpConn } = await serveHttpRaw({
+      http2PriorKnowledge: true,
+    });
+    await client.write(h2ClientPreface());
+    await client.write(h2Frame(0x1, 0x4, 1, [0x83, 0x86, 0x84]));
+    await client.write(h2Frame(0x0, 0, 1, new TextEncoder().encode("body")));
+    const [readRid] = await op_http_accept(httpConn.rid);
+    const chunk = await op_http_read_frame(readRid);
+    assertEquals(new TextDecoder().decode(chunk), "body");
+    await assertRejects(() => op_http_request_trailers(readRid));
+
+    // `grpc-status: 0` as a literal header field with a new name, ending the
+    // stream.
+    const trailer = [0x00, 11, ...new TextEncoder().encode("grpc-status")];
+    await client.write(h2Frame(0x1, 0x5, 1, [...trailer, 1, 0x30]));
+    assertEquals(await op_http_read_frame(readRid), null);
+    assertEquals(await op_http_request_trailers(readRid), [
+      ["grpc-status", "0"],
+    ]);
+    client.close();
+    httpConn.close();
+  },
+);
+
+Deno.test(
+  { permissions: { net: true } },
+  async function httpHttp2ReadFrames() {
+    const { client, httpConn } = await serveHttpRaw({
+      http2PriorKnowledge: true,
+    });
+    const encoder = new TextEncoder();
+    await client.write(h2ClientPreface());
+    await client.write(h2Frame(0x1, 0x4, 1, [0x83, 0x86, 0x84]));
+    await client.write(h2Frame(0x0, 0, 1, encoder.encode("hello")));
+    await client.write(h2Frame(0x0, 0x1, 1, encoder.encode(" world")));
+    const [readRid] = await op_http_accept(httpConn.rid);
+    for (const frame of ["hello", " world"]) {
+      const chunk = await op_http_read_frame(readRid);
+      assertEquals(new TextDecoder().decode(chunk), frame);
+    }
+    assertEquals(await op_http_read_frame(readRid), null);
+    client.close();
+    httpConn.close();
+  },
+);
+
+Deno.test(
+  { permissions: { net: true } },
+  async function httpHttp2UpgradeExtendedConnect() {
+    const { client, httpConn } = await serveHttpRaw({
+      http2PriorKnowledge: true,
+      http2EnableConnectProtocol: true,
+    });
+    const encoder = new TextEncoder();
+    const accept = op_http_accept(httpConn.rid);
+    const frames = h2Frames(client);
+    await client.write(h2ClientPreface());
+    // The server only accepts `:protocol` once its settings are acknowledged,
+    // which `h2Frames()` does as it reads them.
+    await nextH2Frame(frames, 0x4, 0);
+    // Literal header fields for `:method: CONNECT`, `:path: /tunnel`,
+    // `:authority: localhost` and `:protocol: connect-udp`, with
+    // `:scheme: https` from the static table.
+    const literal = (index: number, value: string, name = "") => [
+      index,
+      ...(name ? [name.length, ...encoder.encode(name)] : []),
+      value.length,
+      ...encoder.encode(value),
+    ];
+    await client.write(h2Frame(0x1, 0x4, 1, [
+      ...literal(2, "CONNECT"),
+      0x87,
+      ...literal(4, "/tunnel"),
+      ...literal(1, "localhost"),
+      ...literal(0, "connect-udp", ":protocol"),
+    ]));
+
+    const [readRid, writeRid] = await accept;
+    await assertRejects(
+      () => op_http_upgrade_connect(readRid, "websocket"),
+      Deno.errors.Http,
+      'expected protocol "websocket", got "connect-udp"',
+    );
+    await op_http_write_headers(writeRid, 200, [], "");
+    const tunnelRid = await op_http_upgrade_connect(readRid, "connect-udp");
+    const headers = await nextH2Frame(frames, 0x1, 1);
+    assertEquals(headers.payload[0], 0x88);
+
+    await client.write(h2Frame(0x0, 0, 1, encoder.encode("ping")));
+    const buf = new Uint8Array(16);
+    const n = await core.read(tunnelRid, buf);
+    assertEquals(new TextDecoder().decode(buf.subarray(0, n)), "ping");
+    await core.writeAll(tunnelRid, encoder.encode("pong"));
+    const data = await nextH2Frame(frames, 0x0, 1);
+    assertEquals(new TextDecoder().decode(data.payload), "pong");
+    core.close(tunnelRid);
+    client.close();
+    httpConn.close();
+  },
+);
+
+Deno.test(
+  { permissions: { net: true } },
+  async function httpHttp2ResetStream() {
+    const { client, httpConn } = await serveHttpRaw({
+      http2PriorKnowledge: true,
+    });
+    const frames = h2Frames(client);
+    await client.write(h2ClientPreface());
+    await client.write(h2Frame(0x1, 0x5, 1, [0x82, 0x86, 0x84]));
+    await client.write(h2Frame(0x1, 0x5, 3, [0x82, 0x86, 0x84]));
+    const [, writeA] = await op_http_accept(httpConn.rid);
+    const [, writeB] = await op_http_accept(httpConn.rid);
+
+    await op_http_reset(writeA);
+    const reset = await nextH2Frame(frames, 0x3, 1);
+    // INTERNAL_ERROR.
+    assertEquals(new DataView(reset.payload.buffer).getUint32(0), 0x2);
+
+    // The other stream on the connection is unaffected.
+    await op_http_write_full(writeB, 200, [], new Uint8Array(), []);
+    const headers = await nextH2Frame(frames, 0x1, 3);
+    assertEquals(headers.payload[0], 0x88);
+    client.close();
+    httpConn.close();
+  },
+);
+
+Deno.test(
+  { permissions: { net: true } },
+  async function httpChunkedBufferedResponse() {
+    const { client, httpConn } = await serveHttpRaw();
+    await client.write(
+      new TextEncoder().encode("GET / HTTP/1.1\r\nConnection: close\r\n\r\n"),
+    );
+    const [, writeRid] = await op_http_accept(httpConn.rid);
+    const headers = [["transfer-encoding", "chunked"]];
+    await op_http_write_headers(writeRid, 200, headers, "hello");
+
+    const response = new TextDecoder().decode(await readAll(client));
+    assert(!response.includes("content-length"), response);
+    assert(response.endsWith("\r\n\r\n5\r\nhello\r\n0\r\n\r\n"), response);
+    client.close();
+    httpConn.close();
+  },
+);
+
+Deno.test(
+  { permissions: { net: true } },
+  async function httpHttp10FramedResponse() {
+    const { client, httpConn } = await serveHttpRaw();
+    await client.write(new TextEncoder().encode("GET / HTTP/1.1\r\n\r\n"));
+    const [, writeRid] = await op_http_accept(httpConn.rid);
+    op_http_response_http10(writeRid);
+    await op_http_write_headers(writeRid, 200, [], "hello");
+    assertThrows(
+      () => op_http_response_http10(writeRid),
+      Deno.errors.Http,
+      "response headers already sent",
+    );
+
+    const response = new TextDecoder().decode(await readAll(client));
+    assert(response.startsWith("HTTP/1.0 200 OK\r\n"), response);
+    assert(response.includes("connection: close\r\n"), response);
+    assert(response.endsWith("\r\n\r\nhello"), response);
+    client.close();
+    httpConn.close();
+  },
+);
+
+Deno.test(
+  { permissions: { net: true } },
+  async function httpWriteResponseAsResource() {
+    const { client, httpConn } = await serveHttpRaw();
+    await client.write(
+      new TextEncoder().encode("GET / HTTP/1.1\r\nConnection: close\r\n\r\n"),
+    );
+    const [, writeRid] = await op_http_accept(httpConn.rid);
+    await op_http_write_headers(writeRid, 200, [], null);
+
+    const response = readAll(client);
+    for (const chunk of ["hello", " world"]) {
+      await core.writeAll(writeRid, new TextEncoder().encode(chunk));
+    }
+    await core.shutdown(writeRid);
+    assert(
+      new TextDecoder().decode(await response).endsWith(
+        "5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n",
+      ),
+    );
+    client.close();
+    httpConn.close();
+  },
+);
+
+Deno.test(
+  { permissions: { net: true } },
+  async function httpAbortChunkedResponse() {
+    const { client, httpConn } = await serveHttpRaw();
+    await client.write(
+      new TextEncoder().encode("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n"),
+    );
+    const [, writeRid] = await op_http_accept(httpConn.rid);
+    await assertRejects(() => op_http_response_abort(writeRid));
+
+    await op_http_write_headers(writeRid, 200, [], null);
+    await op_http_write(writeRid, new TextEncoder().encode("hello"));
+    let response = await readResponseUntil(client, "hello\r\n");
+    await op_http_response_abort(writeRid);
+
+    response += new TextDecoder().decode(await readAll(client));
+    assert(response.includes("transfer-encoding: chunked"), response);
+    assert(response.endsWith("5\r\nhello\r\n"), response);
+    await assertRejects(() => op_http_response_abort(writeRid));
+    client.close();
+    httpConn.close();
+  },
+);
+
+Deno.test(
+  { permissions: { net: true } },
+  async function httpMultipartParts() {
+    const { client, httpConn } = await serveHttpRaw();
+    const body = "--b\r\nName: a\r\n\r\nskipped\r\n" +
+      "--b\r\nName: b\r\n\r\nhello world\r\n" +
+      "--b\r\nName: c\r\n\r\n\r\n--b--";
+    await client.write(
+      new TextEncoder().encode(
+        `POST / HTTP/1.1\r\nContent-Length: ${body.length}\r\n\r\n${body}`,
+      ),
+    );
+    const [readRid] = await op_http_accept(httpConn.rid);
+    const multipartRid = op_http_multipart(readRid, "b");
+    const read = async (rid: number, size: number) => {
+      const buf = new Uint8Array(size);
+      const n = await core.read(rid, buf);
+      return new TextDecoder().decode(buf.subarray(0, n));
+    };
+
+    const [headersA, partA] = await op_http_multipart_next(multipartRid);
+    assertEquals(headersA, [["name", "a"]]);
+    const [headersB, partB] = await op_http_multipart_next(multipartRid);
+    assertEquals(headersB, [["name", "b"]]);
+    // The skipped part reads as ended.
+    assertEquals(await read(partA, 16), "");
+    assertEquals(await read(partB, 5), "hello");
+    assertEquals(await read(partB, 16), " world");
+    assertEquals(await read(partB, 16), "");
+    const [, partC] = await op_http_multipart_next(multipartRid);
+    assertEquals(await read(partC, 16), "");
+    assertEquals(await op_http_multipart_next(multipartRid), null);
+    for (const rid of [partA, partB, partC, multipartRid]) {
+      core.close(rid);
+    }
+    client.close();
+    httpConn.close();
+  },
+);
+
+Deno.test(
+  { permissions: { net: true } },
+  async function httpCancelReason() {
+    {
+      const { client, httpConn } = await serveHttpRaw();
+      const reason = op_http_request_on_cancel(httpConn.rid);
+      await op_http_graceful_shutdown(httpConn.rid);
+      assertEquals(await reason, "shutdown");
+      client.close();
+      httpConn.close();
+    }
+    {
+      const { client, httpConn } = await serveHttpRaw();
+      await client.write(new TextEncoder().encode("GET / HTTP/1.1\r\n\r\n"));
+      const [, writeRid] = await op_http_accept(httpConn.rid);
+      // HTTP/1 responses are reset by closing the connection.
+      await op_http_reset(writeRid);
+      assertEquals(await op_http_request_on_cancel(httpConn.rid), "abort");
+      client.close();
+      httpConn.close();
+    }
+    {
+      const { client, httpConn } = await serveHttpRaw();
+      client.close();
+      const [reason] = await Promise.all([
+        op_http_request_on_cancel(httpConn.rid),
+        op_http_accept(httpConn.rid),
+      ]);
+      assertEquals(reason, "disconnect");
+      httpConn.close();
+    }
+  },
+);
+
+Deno.test(
+  { permissions: { net: true } },
+  async function httpInlineBody() {
+    const requests = [
+      ["Content-Length: 5\r\n\r\nhello", 16, true],
+      ["Content-Length: 5\r\n\r\nhello", 4, false],
+      // The body is still arriving.
+      ["Content-Length: 10\r\n\r\nhello", 16, false],
+      ["Transfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n", 16, false],
+    ] as const;
+    for (const [request, inlineBodySize, inlined] of requests) {
+      const { client, httpConn } = await serveHttpRaw({ inlineBodySize });
+      await client.write(
+        new TextEncoder().encode(`POST / HTTP/1.1\r\n${request}`),
+      );
+      const [readRid, , , , , body] = await op_http_accept(httpConn.rid);
+      if (inlined) {
+        assertEquals(new TextDecoder().decode(body), "hello");
+      } else {
+        // Bodies that are not inlined are left to be read.
+        assertEquals(body, null);
+        const chunk = await op_http_read_chunk(readRid, 16);
+        assertEquals(new TextDecoder().decode(chunk), "hello");
+      }
+      client.close();
+      httpConn.close();
+    }
+  },
+);
+
+Deno.test(
+  { permissions: { net: true } },
+  async function httpBodyConsumedAfterReading() {
+    const { client, httpConn } = await serveHttpRaw();
+    await client.write(
+      new TextEncoder().encode(
+        "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n",
+      ),
+    );
+    await client.write(new TextEncoder().encode("4\r\nbody\r\n0\r\n\r\n"));
+    const [readRid] = await op_http_accept(httpConn.rid);
+    const chunk = await op_http_read_chunk(readRid, 16);
+    assertEquals(new TextDecoder().decode(chunk), "body");
+    assert(!op_http_body_consumed(readRid));
+    assertEquals(await op_http_read_chunk(readRid, 16), null);
+    assert(op_http_body_consumed(readRid));
+    client.close();
+    httpConn.close();
+  },
+);
+
+Deno.test(
+  { permissions: { net: true } },
+  async function httpContentLengthMismatch() {
+    const { client, httpConn } = await serveHttpRaw();
+    await client.write(
+      new TextEncoder().encode("GET / HTTP/1.1\r\nConnection: close\r\n\r\n"),
+    );
+    const [, writeRid] = await op_http_accept(httpConn.rid);
+    const header = (length: string) => [["content-length", length]];
+    await assertRejects(
+      () => op_http_write_headers(writeRid, 200, header("4"), "hello"),
+      Deno.errors.Http,
+    );
+    const body = new TextEncoder().encode("hello");
+    await assertRejects(
+      () => op_http_write_full(writeRid, 200, header("4"), body, []),
+      Deno.errors.Http,
+    );
+    await op_http_write_full(writeRid, 200, header("5"), body, []);
+
+    const response = new TextDecoder().decode(await readAll(client));
+    assert(response.includes("content-length: 5\r\n"), response);
+    assert(response.endsWith("\r\n\r\nhello"), response);
+    client.close();
+    httpConn.close();
+  },
+);
+
 function chunkedBodyReader(h: Headers, r: BufReader): Deno.Reader {
   // Based on https://tools.ietf.org/html/rfc2616#section-19.4.6
   const tp = new TextProtoReader(r);
@@ -2854,3 +3852,111 @@ function isProhibitedForTrailer(key: string): boolean {
   const s = new Set(["transfer-encoding", "content-length", "trailer"]);
   return s.has(key.toLowerCase());
 }
+
+/** Serves a TCP connection with `Deno.serveHttp` and returns its client end,
+ * for tests that write raw requests. */
+async function serveHttpRaw(
+  options?: Deno.HttpConnOptions,
+): Promise<{ client: Deno.Conn; httpConn: Deno.HttpConn }> {
+  const listener = Deno.listen({ hostname: "127.0.0.1", port: listenPort });
+  const [client, conn] = await Promise.all([
+    Deno.connect({ hostname: "127.0.0.1", port: listenPort }),
+    listener.accept(),
+  ]);
+  listener.close();
+  return { client, httpConn: Deno.serveHttp(conn, options) };
+}
+
+/** Reads from `conn` until what was read ends with `suffix`. */
+async function readResponseUntil(
+  conn: Deno.Conn,
+  suffix: string,
+): Promise<string> {
+  const decoder = new TextDecoder();
+  const buf = new Uint8Array(1024);
+  let response = "";
+  while (!response.endsWith(suffix)) {
+    const n = await conn.read(buf);
+    assert(n !== null, response);
+    response += decoder.decode(buf.subarray(0, n), { stream: true });
+  }
+  return response;
+}
+
+interface H2Frame {
+  type: number;
+  flags: number;
+  stream: number;
+  payload: Uint8Array;
+}
+
+/** Encodes an HTTP/2 frame, for tests that speak HTTP/2 without a client. */
+function h2Frame(
+  type: number,
+  flags: number,
+  stream: number,
+  payload: ArrayLike<number> = [],
+): Uint8Array {
+  const frame = new Uint8Array(9 + payload.length);
+  const view = new DataView(frame.buffer);
+  view.setUint32(0, (payload.length << 8) | type);
+  view.setUint8(4, flags);
+  view.setUint32(5, stream);
+  frame.set(payload, 9);
+  return frame;
+}
+
+/** The HTTP/2 connection preface, with empty settings. */
+function h2ClientPreface(): Uint8Array {
+  return concat([
+    new TextEncoder().encode("PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n"),
+    h2Frame(0x4, 0, 0),
+  ]);
+}
+
+/** Reads the HTTP/2 frames that the server sends on `conn`, acknowledging its
+ * settings as they arrive. */
+async function* h2Frames(conn: Deno.Conn): AsyncGenerator<H2Frame> {
+  const chunk = new Uint8Array(16 * 1024);
+  let buf = new Uint8Array();
+  while (true) {
+    while (buf.length >= 9) {
+      const length = (buf[0] << 16) | (buf[1] << 8) | buf[2];
+      if (buf.length < 9 + length) {
+        break;
+      }
+      const view = new DataView(buf.buffer, buf.byteOffset);
+      const frame = {
+        type: buf[3],
+        flags: buf[4],
+        stream: view.getUint32(5) & 0x7fffffff,
+        payload: buf.slice(9, 9 + length),
+      };
+      buf = buf.slice(9 + length);
+      if (frame.type === 0x4 && !(frame.flags & 0x1)) {
+        await conn.write(h2Frame(0x4, 0x1, 0));
+      }
+      yield frame;
+    }
+    const n = await conn.read(chunk);
+    if (n === null) {
+      return;
+    }
+    buf = concat([buf, chunk.subarray(0, n)]);
+  }
+}
+
+/** Returns the next frame of `type` on `stream`, skipping the others. */
+async function nextH2Frame(
+  frames: AsyncGenerator<H2Frame>,
+  type: number,
+  stream: number,
+): Promise<H2Frame> {
+  while (true) {
+    const { value, done } = await frames.next();
+    assert(!done, "connection closed");
+    if (value.type === type && value.stream === stream) {
+      return value;
+    }
+  }
+}